@@ -0,0 +1,572 @@
+//bytecode.rs - compiles the syntax tree to bytecode for the vm (vm.rs), used with --vm
+//every top-level command of a block becomes its own chunk, so blocks still run
+//command by command like with Command::execute. every variable and field name
+//is resolved to a slot of the program's Layout while compiling. individuals
+//spawned by the vm keep their values in those slots (types::Store), so the vm
+//reads and writes them by number, without looking up names.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::types::*;
+use crate::spatial::Metric;
+
+//math operators, resolved once instead of comparing strings on every evaluation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Other, //unknown operator, evaluates to 0 like in eval.rs
+}
+
+impl Arith {
+    fn from_symbol(op: &str) -> Self {
+        match op {
+            "+" => Arith::Add,
+            "-" => Arith::Sub,
+            "*" => Arith::Mul,
+            "/" => Arith::Div,
+            "%" => Arith::Rem,
+            _ => Arith::Other,
+        }
+    }
+
+    //back to the symbol eval::binary_op understands
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Arith::Add => "+",
+            Arith::Sub => "-",
+            Arith::Mul => "*",
+            Arith::Div => "/",
+            Arith::Rem => "%",
+            Arith::Other => "?",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+}
+
+//one instruction. the vm is a stack machine: operands are popped, results pushed.
+//jump targets are indexes into the chunk's code
+#[derive(Debug, Clone)]
+pub enum Op {
+    //values
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    LoadSlot(usize),          //variable in a slot
+    Field(usize, usize),      //pop an object, push the field in a slot (slot, line)
+    MakeList(usize),          //pop n values, push them as a list
+    Index(usize),             //pop index and collection, push the element (line)
+    ToInt,                    //int context, what Exp::eval gives instead of eval_to_val
+    Arith(Arith, usize),      //pop right and left (line)
+    Compare(Compare),         //pop right and left, push a Bool
+
+    //control flow
+    Jump(usize),
+    JumpIfFalse(usize), //pops the condition
+    JumpIfTrue(usize),  //pops the condition
+    Pop,
+    Check,  //stop the command with the pending runtime error, if any
+    Return, //pop the value and leave the chunk
+
+    //commands
+    StoreSlot(usize),                //pop into the variable in a slot
+    SetField(usize, usize),          //pop object then value into the field in a slot (slot, line)
+    SkipUnlessList(usize, usize),    //index assignment on something that isn't a list: raise, drop both, jump (target, line)
+    SetIndex(usize),                 //pop index, list and value (line)
+    Spawn(String),                   //pop y and x
+    Die,
+    Print(usize),                    //pop n values
+    ForNext(usize, usize),           //next living individual into a slot, or jump when done. the position is on the stack
+
+    //builtins, arguments are already on the stack
+    Len,
+    IfList(usize), //push(): leave 0 and jump unless the top is a list
+    ListPush,
+    ListPop,
+    GetAt,
+    AllAt,
+    Nearest,
+    CountInRadius,
+    Neighbors(Metric),
+    Kill,
+    Dist,
+    Random,
+    DrawRect(usize),   //number of arguments given
+    DrawLine(usize),
+    DrawCircle(usize),
+    CallCheck(usize, usize, usize), //recursion limit hit: leave 0 and jump (function, target, line)
+    Call(usize, usize),             //pop the arguments and run a FUNCTION (function, argument count)
+    UnknownFunction(String, usize),
+}
+
+//compiled code of one command (or one FUNCTION body / property default)
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub params: Vec<usize>, //slot of each parameter in the call's frame
+    pub body: Chunk,
+}
+
+//which block of the program a command belongs to
+#[derive(Debug, Clone, Copy)]
+pub enum Block<'a> {
+    Spawn,
+    Routine(&'a str),
+    Fitness,
+    Mutation(&'a str), //by action, the first rule with that name like the callers pick
+    Visualize,
+}
+
+//the whole program compiled, one chunk per top-level command of each block
+#[derive(Debug, Clone, Default)]
+pub struct Bytecode {
+    pub spawn: Vec<Chunk>,
+    pub routines: HashMap<String, Vec<Chunk>>,
+    pub fitness: Vec<Chunk>,
+    pub mutations: HashMap<String, Vec<Chunk>>,
+    pub visualize: Vec<Chunk>,
+    pub functions: Vec<CompiledFunction>,
+    pub species: HashMap<String, Vec<(usize, Chunk)>>, //default property values by slot, in spawn order
+    pub layout: Arc<Layout>,                            //slot of every name the chunks use
+}
+
+impl Bytecode {
+    pub fn block(&self, block: Block) -> Option<&[Chunk]> {
+        match block {
+            Block::Spawn => Some(&self.spawn),
+            Block::Routine(name) => self.routines.get(name).map(|c| c.as_slice()),
+            Block::Fitness => Some(&self.fitness),
+            Block::Mutation(action) => self.mutations.get(action).map(|c| c.as_slice()),
+            Block::Visualize => Some(&self.visualize),
+        }
+    }
+}
+
+//compile every block of a (validated) program
+pub fn compile(program: &Program) -> Bytecode {
+    //functions are called by index
+    let mut function_ids = HashMap::new();
+    let mut function_defs = Vec::new();
+    for (name, func) in &program.functions_block {
+        function_ids.insert(name.clone(), function_defs.len());
+        function_defs.push(func);
+    }
+
+    //species properties get the first slots after the fixed ones
+    let mut layout = Layout::default();
+    for species in program.species_block.values() {
+        for (prop, _) in &species.properties {
+            layout.add(prop);
+        }
+    }
+
+    let block = |commands: &[Command], layout: &mut Layout| -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        for cmd in commands {
+            let mut compiler = Compiler::new(program, &function_ids, layout);
+            compiler.command(cmd);
+            chunks.push(compiler.finish());
+        }
+        chunks
+    };
+
+    let mut code = Bytecode {
+        spawn: block(&program.spawns_block, &mut layout),
+        fitness: block(&program.fitness_block.commands, &mut layout),
+        visualize: block(&program.visualize_block, &mut layout),
+        ..Default::default()
+    };
+    for (name, routine) in &program.routines_block {
+        code.routines.insert(name.clone(), block(&routine.body, &mut layout));
+    }
+    for rule in &program.mutations_block {
+        if !code.mutations.contains_key(&rule.action) {
+            let chunks = rule.body.as_ref().map(|body| block(body, &mut layout)).unwrap_or_default();
+            code.mutations.insert(rule.action.clone(), chunks);
+        }
+    }
+
+    for func in function_defs {
+        let params = func.params.iter().map(|param| layout.add(param)).collect();
+        let mut compiler = Compiler::new(program, &function_ids, &mut layout);
+        for cmd in &func.body {
+            compiler.command(cmd);
+        }
+        code.functions.push(CompiledFunction {
+            name: func.name.clone(),
+            params,
+            body: compiler.finish(),
+        });
+    }
+
+    for (name, species) in &program.species_block {
+        let mut defaults = Vec::new();
+        for (prop, exp) in &species.properties {
+            let mut compiler = Compiler::new(program, &function_ids, &mut layout);
+            compiler.exp(exp);
+            compiler.emit(Op::Return);
+            let chunk = compiler.finish();
+            defaults.push((layout.add(prop), chunk));
+        }
+        code.species.insert(name.clone(), defaults);
+    }
+    code.layout = Arc::new(layout);
+    code
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    functions: &'a HashMap<String, usize>,
+    layout: &'a mut Layout,
+    code: Vec<Op>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(program: &'a Program, functions: &'a HashMap<String, usize>, layout: &'a mut Layout) -> Self {
+        Self { program, functions, layout, code: Vec::new() }
+    }
+
+    fn finish(self) -> Chunk {
+        Chunk { code: self.code }
+    }
+
+    fn slot(&mut self, name: &str) -> usize {
+        self.layout.add(name)
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.code.len()
+    }
+
+    //point a jump emitted earlier at the current position
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.code[at] {
+            Op::Jump(target) |
+            Op::JumpIfFalse(target) |
+            Op::JumpIfTrue(target) |
+            Op::SkipUnlessList(target, _) |
+            Op::ForNext(_, target) |
+            Op::IfList(target) |
+            Op::CallCheck(_, target, _) => *target = here,
+            _ => {}
+        }
+    }
+
+    //same as Exp::eval_to_val
+    fn exp(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Int(v, _) => { self.emit(Op::Int(*v)); }
+            Exp::Float(f, _) => { self.emit(Op::Float(*f)); }
+            Exp::Bool(b, _) => { self.emit(Op::Bool(*b)); }
+            Exp::StringLiteral(s, _) => { self.emit(Op::Str(s.clone())); }
+            Exp::Var(name, _) => {
+                let slot = self.slot(name);
+                self.emit(Op::LoadSlot(slot));
+            }
+            Exp::Dot(obj, field, span) => {
+                self.exp(obj);
+                let slot = self.slot(field);
+                self.emit(Op::Field(slot, span.line));
+            }
+            Exp::BinaryOp(left, op, right, span) => {
+                self.exp(left);
+                self.exp(right);
+                self.emit(Op::Arith(Arith::from_symbol(op), span.line));
+            }
+            Exp::Call(name, args, span) => self.call(name, args, span.line),
+            Exp::Index(list, idx, span) => {
+                self.exp(list);
+                self.int(idx);
+                self.emit(Op::Index(span.line));
+            }
+            Exp::List(items, _) => {
+                for item in items {
+                    self.exp(item);
+                }
+                self.emit(Op::MakeList(items.len()));
+            }
+        }
+    }
+
+    //same as Exp::eval (strings and list literals are 0 there without being evaluated)
+    fn int(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Int(v, _) => { self.emit(Op::Int(*v)); }
+            Exp::Float(f, _) => { self.emit(Op::Int(*f as i32)); }
+            Exp::Bool(b, _) => { self.emit(Op::Int(if *b { 1 } else { 0 })); }
+            Exp::StringLiteral(..) | Exp::List(..) => { self.emit(Op::Int(0)); }
+            _ => {
+                self.exp(exp);
+                self.emit(Op::ToInt);
+            }
+        }
+    }
+
+    //builtins take the same arguments, in the same order, as run_builtin evaluates them
+    fn call(&mut self, name: &str, args: &[Exp], line: usize) {
+        match name {
+            "len" | "pop" | "kill" if !args.is_empty() => {
+                self.exp(&args[0]);
+                self.emit(match name {
+                    "len" => Op::Len,
+                    "pop" => Op::ListPop,
+                    _ => Op::Kill,
+                });
+            }
+            "push" if args.len() >= 2 => {
+                self.exp(&args[0]);
+                let skip = self.emit(Op::IfList(0));
+                self.exp(&args[1]);
+                self.emit(Op::ListPush);
+                self.patch(skip);
+            }
+            "get_at" | "all_at" if args.len() >= 2 => {
+                self.int(&args[0]);
+                self.int(&args[1]);
+                self.emit(if name == "get_at" { Op::GetAt } else { Op::AllAt });
+            }
+            "nearest" | "count_in_radius" if args.len() >= 2 => {
+                self.exp(&args[0]);
+                self.int(&args[1]);
+                self.emit(if name == "nearest" { Op::Nearest } else { Op::CountInRadius });
+            }
+            "neighbors" | "von_neumann" | "moore" if !args.is_empty() => {
+                self.int(&args[0]);
+                let metric = if name == "moore" { Metric::Chebyshev } else { Metric::Manhattan };
+                self.emit(Op::Neighbors(metric));
+            }
+            "dist" if args.len() >= 2 => {
+                self.exp(&args[0]);
+                self.exp(&args[1]);
+                self.emit(Op::Dist);
+            }
+            "random" if args.len() == 2 => {
+                self.exp(&args[0]);
+                self.exp(&args[1]);
+                self.emit(Op::Random);
+            }
+            //positions and sizes are floats, colors ints
+            "draw_rect" if args.len() >= 4 => {
+                let count = args.len().min(7);
+                for (i, arg) in args[..count].iter().enumerate() {
+                    if i < 4 { self.exp(arg) } else { self.int(arg) }
+                }
+                self.emit(Op::DrawRect(count));
+            }
+            "draw_line" if args.len() >= 4 => {
+                let count = args.len().min(8);
+                for (i, arg) in args[..count].iter().enumerate() {
+                    if i < 4 || i == 7 { self.exp(arg) } else { self.int(arg) }
+                }
+                self.emit(Op::DrawLine(count));
+            }
+            "draw_circle" if args.len() >= 3 => {
+                let count = args.len().min(6);
+                for (i, arg) in args[..count].iter().enumerate() {
+                    if i < 3 { self.exp(arg) } else { self.int(arg) }
+                }
+                self.emit(Op::DrawCircle(count));
+            }
+            //called with too few arguments
+            "all_at" | "neighbors" | "von_neumann" | "moore" => { self.emit(Op::MakeList(0)); }
+            "len" | "pop" | "kill" | "push" | "get_at" | "nearest" | "count_in_radius" |
+            "dist" | "random" | "draw_rect" | "draw_line" | "draw_circle" => { self.emit(Op::Int(0)); }
+
+            //user FUNCTION
+            _ => match self.functions.get(name) {
+                Some(&func) => {
+                    let skip = self.emit(Op::CallCheck(func, 0, line));
+                    for arg in args {
+                        self.exp(arg);
+                    }
+                    self.emit(Op::Call(func, args.len()));
+                    self.patch(skip);
+                }
+                None => { self.emit(Op::UnknownFunction(name.to_string(), line)); }
+            },
+        }
+    }
+
+    //pushes the condition as a Bool. && and || skip the right side like BExp::eval
+    fn bexp(&mut self, bexp: &BExp) {
+        let (left, right, compare) = match bexp {
+            BExp::And(left, right) | BExp::Or(left, right) => {
+                let is_and = matches!(bexp, BExp::And(..));
+                self.bexp(left);
+                let short = self.emit(if is_and { Op::JumpIfFalse(0) } else { Op::JumpIfTrue(0) });
+                self.bexp(right);
+                let done = self.emit(Op::Jump(0));
+                self.patch(short);
+                self.emit(Op::Bool(!is_and));
+                self.patch(done);
+                return;
+            }
+            BExp::Equal(l, r) => (l, r, Compare::Equal),
+            BExp::NotEqual(l, r) => (l, r, Compare::NotEqual),
+            BExp::Greater(l, r) => (l, r, Compare::Greater),
+            BExp::Less(l, r) => (l, r, Compare::Less),
+            BExp::GreaterEqual(l, r) => (l, r, Compare::GreaterEqual),
+            BExp::LessEqual(l, r) => (l, r, Compare::LessEqual),
+        };
+        self.exp(left);
+        self.exp(right);
+        self.emit(Op::Compare(compare));
+    }
+
+    fn commands(&mut self, commands: &[Command]) {
+        for cmd in commands {
+            self.command(cmd);
+        }
+    }
+
+    //every command leaves the stack as it found it.
+    //Check goes exactly where Command::execute calls check_error
+    fn command(&mut self, cmd: &Command) {
+        match cmd {
+            Command::Exp(exp, _) => {
+                self.exp(exp);
+                self.emit(Op::Check);
+                self.emit(Op::Pop);
+            }
+            Command::Spawn { species, x, y, .. } => {
+                //unknown species spawn nothing and evaluate nothing
+                if self.program.species_block.contains_key(species) {
+                    self.exp(x);
+                    self.exp(y);
+                    self.emit(Op::Check);
+                    self.emit(Op::Spawn(species.clone()));
+                    self.emit(Op::Check);
+                }
+            }
+            Command::Die(_) => { self.emit(Op::Die); }
+            Command::Print(exps, _) => {
+                for exp in exps {
+                    self.exp(exp);
+                }
+                self.emit(Op::Check);
+                self.emit(Op::Print(exps.len()));
+            }
+            Command::Assign { target, value, line } => {
+                self.exp(value);
+                self.emit(Op::Check);
+                match target {
+                    Exp::Var(name, _) => {
+                        let slot = self.slot(name);
+                        self.emit(Op::StoreSlot(slot));
+                    }
+                    Exp::Dot(obj, field, _) => {
+                        self.exp(obj);
+                        let slot = self.slot(field);
+                        self.emit(Op::SetField(slot, *line));
+                    }
+                    Exp::Index(list, idx, _) => {
+                        self.exp(list);
+                        let skip = self.emit(Op::SkipUnlessList(0, *line));
+                        self.int(idx);
+                        self.emit(Op::SetIndex(*line));
+                        self.patch(skip);
+                    }
+                    _ => { self.emit(Op::Pop); }
+                }
+                self.emit(Op::Check);
+            }
+            Command::If { condition, then_block, else_block, .. } => {
+                self.bexp(condition);
+                self.emit(Op::Check);
+                let skip_then = self.emit(Op::JumpIfFalse(0));
+                self.commands(then_block);
+                match else_block {
+                    Some(else_cmds) => {
+                        let skip_else = self.emit(Op::Jump(0));
+                        self.patch(skip_then);
+                        self.commands(else_cmds);
+                        self.patch(skip_else);
+                    }
+                    None => self.patch(skip_then),
+                }
+            }
+            Command::While { condition, body, .. } => {
+                let top = self.here();
+                self.bexp(condition);
+                self.emit(Op::Check);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.commands(body);
+                self.emit(Op::Jump(top));
+                self.patch(exit);
+            }
+            //only `for x in environment` runs anything, like in eval.rs
+            Command::For { var, collection, body, .. } => {
+                if collection == "environment" {
+                    let slot = self.slot(var);
+                    self.emit(Op::Int(0));
+                    let top = self.emit(Op::ForNext(slot, 0));
+                    self.commands(body);
+                    self.emit(Op::Jump(top));
+                    self.patch(top);
+                }
+            }
+            Command::Return(exp, _) => {
+                self.exp(exp);
+                self.emit(Op::Check);
+                self.emit(Op::Return);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+
+    #[test]
+    fn variables_and_fields_compile_to_layout_slots() {
+        let source = "
+            ENVIRONMENT { width: 5, height: 5, steps: 1 }
+            SPECIES {
+                Cell { power: 1, routine: grow }
+                ROUTINE grow { self.power = power + 1; }
+            }
+            SPAWN { spawn Cell @ (0, 0); }
+            FITNESS { return self.power; }
+            MUTATE { mutation: { } }
+            EVOLVE { generations: 1, instances: 1 }
+        ";
+        let (program, errors) = Parser::new(lex(source).0).parse_program();
+        assert!(errors.is_empty());
+        let code = compile(&program);
+        let power = code.layout.slot("power").unwrap();
+
+        let ops = &code.routines["grow"][0].code;
+        assert!(matches!(ops[0], Op::LoadSlot(slot) if slot == power));
+        assert!(ops.iter().any(|op| matches!(op, Op::LoadSlot(Layout::SELF))));
+        assert!(ops.iter().any(|op| matches!(op, Op::SetField(slot, _) if *slot == power)));
+        assert_eq!(code.species["Cell"][0].0, power);
+    }
+}
@@ -0,0 +1,369 @@
+//checkpoint.rs - save and resume a run between generations
+//a checkpoint is a plain text file with the generation counter, global best
+//and every world's individuals (with their ids) and rng position. it is taken right after the
+//next generation was created, so resuming continues exactly where it stopped.
+//the program itself is not saved: resume with the same source file.
+
+use std::sync::{Arc, RwLock};
+use rand_chacha::ChaCha12Rng;
+use rand::SeedableRng;
+
+use crate::types::*;
+use crate::simulation::Simulation;
+
+const HEADER: &str = "simulanka-checkpoint 2";
+
+//a world as it was saved, waiting to be put back into a simulation
+struct SavedWorld {
+    id: i32,
+    generation: i32,
+    fitness: i32,
+    rng: ChaCha12Rng,
+    individuals: Vec<Individual>,
+}
+
+pub struct Checkpoint {
+    pub seed: u64,
+    pub generation: i32,
+    pub global_best: i32,
+    next_id: u64, //id counter of the saved run
+    worlds: Vec<SavedWorld>,
+}
+
+//writing
+
+//write the simulation state to `path` (through a temp file so a crash
+//while saving never leaves a half written checkpoint behind)
+pub fn save(sim: &Simulation, path: &str) -> Result<(), String> {
+    let mut out = String::new();
+    out.push_str(HEADER);
+    out.push('\n');
+    out.push_str(&format!("seed {}\n", sim.program.evolve_block.seed.unwrap_or(0)));
+    out.push_str(&format!("generation {}\n", sim.current_gen));
+    out.push_str(&format!("global_best {}\n", sim.global_best_fitness));
+    out.push_str(&format!("next_id {}\n", Individual::next_id()));
+    out.push_str(&format!("worlds {}\n", sim.instances.len()));
+
+    for world in &sim.instances {
+        out.push_str(&format!("world {} {} {}\n", world.id, world.generation, world.fitness));
+        let seed: String = world.rng.get_seed().iter().map(|b| format!("{:02x}", b)).collect();
+        out.push_str(&format!("rng {} {} {}\n", seed, world.rng.get_stream(), world.rng.get_word_pos()));
+        out.push_str(&format!("individuals {}\n", world.individuals.len()));
+
+        for ind in &world.individuals {
+            out.push_str(&format!("ind {} {}\n", ind.id, ind.species));
+            let env = ind.env.read().unwrap();
+            //sorted so the same state always gives the same file
+            let mut keys: Vec<&String> = env.store.keys().collect();
+            keys.sort();
+            for key in keys {
+                let mut line = format!("var {}", key);
+                write_value(&env.store[key], &world.individuals, &mut line);
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+    }
+
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, out).map_err(|e| format!("Cannot write {}: {}", tmp, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Cannot write {}: {}", path, e))
+}
+
+//values become space separated tokens. objects are saved as the index of
+//the individual they point to, strings as hex so they can hold spaces
+fn write_value(value: &Value, individuals: &[Individual], out: &mut String) {
+    match value {
+        Value::Int(v) => out.push_str(&format!(" i {}", v)),
+        Value::Float(f) => out.push_str(&format!(" f {:016x}", f.to_bits())),
+        Value::Bool(b) => out.push_str(if *b { " b 1" } else { " b 0" }),
+        Value::String(s) => {
+            let hex: String = s.bytes().map(|b| format!("{:02x}", b)).collect();
+            out.push_str(&format!(" s {}", if hex.is_empty() { "-".to_string() } else { hex }));
+        }
+        Value::Object(obj) => {
+            let idx = individuals.iter().position(|ind| Arc::ptr_eq(&ind.env, obj));
+            out.push_str(&format!(" o {}", idx.map_or(-1, |i| i as i64)));
+        }
+        Value::List(list) => {
+            let list = list.read().unwrap();
+            out.push_str(&format!(" l {}", list.len()));
+            for v in list.iter() {
+                write_value(v, individuals, out);
+            }
+        }
+        Value::Environment => out.push_str(" e"),
+        Value::GridRow(row) => out.push_str(&format!(" g {}", row)),
+    }
+}
+
+//reading
+
+//line reader that checks each line starts with the expected keyword
+struct Reader<'a> {
+    lines: std::iter::Peekable<std::iter::Enumerate<std::str::Lines<'a>>>,
+}
+
+impl<'a> Reader<'a> {
+    //the tokens after `keyword` on the next line
+    fn expect(&mut self, keyword: &str) -> Result<Vec<String>, String> {
+        let (n, line) = self.lines.next().ok_or(format!("Checkpoint ends early, expected '{}'", keyword))?;
+        let mut tokens = line.split_whitespace().map(|t| t.to_string());
+        if tokens.next().as_deref() != Some(keyword) {
+            return Err(format!("Checkpoint line {}: expected '{}'", n + 1, keyword));
+        }
+        Ok(tokens.collect())
+    }
+
+    //the next `var` line of the current individual, if any
+    fn next_var(&mut self) -> Option<(usize, &'a str)> {
+        if self.lines.peek().is_some_and(|(_, l)| l.starts_with("var ")) {
+            self.lines.next()
+        } else {
+            None
+        }
+    }
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        let mut reader = Reader { lines: text.lines().enumerate().peekable() };
+
+        if reader.expect("simulanka-checkpoint")? != ["2"] {
+            return Err(format!("{} is not a version 2 checkpoint", path));
+        }
+        let seed = parse_num(&reader.expect("seed")?, 0)?;
+        let generation = parse_num(&reader.expect("generation")?, 0)?;
+        let global_best = parse_num(&reader.expect("global_best")?, 0)?;
+        let next_id = parse_num(&reader.expect("next_id")?, 0)?;
+        let world_count: usize = parse_num(&reader.expect("worlds")?, 0)?;
+
+        let mut worlds = Vec::new();
+        for _ in 0..world_count {
+            let header = reader.expect("world")?;
+            let id = parse_num(&header, 0)?;
+            let world_gen = parse_num(&header, 1)?;
+            let fitness = parse_num(&header, 2)?;
+
+            let rng_line = reader.expect("rng")?;
+            let seed_bytes = parse_hex(rng_line.first().map_or("", |s| s.as_str()))?;
+            let rng_seed: [u8; 32] = seed_bytes.try_into().map_err(|_| "Bad rng seed in checkpoint".to_string())?;
+            let mut rng = ChaCha12Rng::from_seed(rng_seed);
+            rng.set_stream(parse_num(&rng_line, 1)?);
+            rng.set_word_pos(parse_num(&rng_line, 2)?);
+
+            //create every environment first so objects can point forward
+            let count: usize = parse_num(&reader.expect("individuals")?, 0)?;
+            let envs: Vec<Arc<RwLock<Environment>>> = (0..count).map(|_| Environment::new()).collect();
+            let mut individuals = Vec::new();
+
+            for env in &envs {
+                let header = reader.expect("ind")?;
+                let id = parse_num(&header, 0)?;
+                let species = header[1..].join(" ");
+                let mut store = Store::default();
+                while let Some((n, line)) = reader.next_var() {
+                    let mut tokens = line.split_whitespace().skip(1);
+                    let key = tokens.next().ok_or(format!("Checkpoint line {}: missing variable name", n + 1))?;
+                    let value = read_value(&mut tokens, &envs)
+                        .map_err(|e| format!("Checkpoint line {}: {}", n + 1, e))?;
+                    store.insert(key.to_string(), value);
+                }
+                env.write().unwrap().store = store;
+                individuals.push(Individual { id, species, env: env.clone() });
+            }
+
+            worlds.push(SavedWorld { id, generation: world_gen, fitness, rng, individuals });
+        }
+
+        Ok(Checkpoint { seed, generation, global_best, next_id, worlds })
+    }
+
+    //install the saved worlds in a simulation (made with Simulation::without_worlds).
+    //the simulation must run the same program with the seed from the checkpoint
+    pub fn restore(self, sim: &mut Simulation) -> Result<(), String> {
+        if self.worlds.len() != sim.num_instances as usize {
+            return Err(format!(
+                "Checkpoint has {} instances but EVOLVE asks for {}",
+                self.worlds.len(), sim.num_instances
+            ));
+        }
+        for saved in &self.worlds {
+            for ind in &saved.individuals {
+                if !sim.program.species_block.contains_key(&ind.species) {
+                    return Err(format!("Checkpoint has unknown species '{}'", ind.species));
+                }
+            }
+        }
+
+        let mut instances = Vec::new();
+        for saved in self.worlds {
+            let mut world = World::new(sim.program.clone(), saved.id);
+            world.generation = saved.generation;
+            world.fitness = saved.fitness;
+            world.rng = saved.rng;
+            world.individuals = saved.individuals;
+            //the vm reads properties from the slots its compile gave them
+            if let Some(code) = &sim.program.bytecode {
+                for ind in &world.individuals {
+                    ind.env.write().unwrap().store.adopt(&code.layout);
+                }
+            }
+            instances.push(world);
+        }
+
+        Individual::reserve_ids(self.next_id);
+        crate::evolution::clear_generation_memory(&mut sim.instances);
+        sim.instances = instances;
+        sim.current_gen = self.generation;
+        sim.global_best_fitness = self.global_best;
+        Ok(())
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(tokens: &[String], idx: usize) -> Result<T, String> {
+    tokens.get(idx)
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| format!("Bad number in checkpoint: {:?}", tokens.get(idx)))
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s == "-" {
+        return Ok(Vec::new());
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("Bad hex in checkpoint: {}", s));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("Bad hex in checkpoint: {}", s)))
+        .collect()
+}
+
+fn next_token<'a>(tokens: &mut impl Iterator<Item = &'a str>, kind: &str) -> Result<&'a str, String> {
+    tokens.next().ok_or(format!("missing data for '{}' value", kind))
+}
+
+fn read_value<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    envs: &[Arc<RwLock<Environment>>],
+) -> Result<Value, String> {
+    let kind = tokens.next().ok_or("missing value")?;
+    let bad = |t: &str| format!("bad '{}' value: {}", kind, t);
+
+    Ok(match kind {
+        "i" => {
+            let t = next_token(tokens, kind)?;
+            Value::Int(t.parse().map_err(|_| bad(t))?)
+        }
+        "f" => {
+            let t = next_token(tokens, kind)?;
+            Value::Float(f64::from_bits(u64::from_str_radix(t, 16).map_err(|_| bad(t))?))
+        }
+        "b" => Value::Bool(next_token(tokens, kind)? == "1"),
+        "s" => {
+            let bytes = parse_hex(next_token(tokens, kind)?)?;
+            Value::String(String::from_utf8(bytes).map_err(|_| "string is not utf-8".to_string())?)
+        }
+        "o" => {
+            let t = next_token(tokens, kind)?;
+            let idx: i64 = t.parse().map_err(|_| bad(t))?;
+            match envs.get(idx as usize) {
+                Some(env) if idx >= 0 => Value::Object(env.clone()),
+                //pointed outside this world, same as a missing object
+                _ => Value::Int(0),
+            }
+        }
+        "l" => {
+            let t = next_token(tokens, kind)?;
+            let len: usize = t.parse().map_err(|_| bad(t))?;
+            let mut items = Vec::new();
+            for _ in 0..len {
+                items.push(read_value(tokens, envs)?);
+            }
+            Value::List(Arc::new(RwLock::new(items)))
+        }
+        "e" => Value::Environment,
+        "g" => {
+            let t = next_token(tokens, kind)?;
+            Value::GridRow(t.parse().map_err(|_| bad(t))?)
+        }
+        _ => return Err(format!("unknown value type '{}'", kind)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+    use crate::semantic::validate_program;
+    use crate::diagnostic::has_errors;
+    use crate::bytecode::compile;
+
+    const SOURCE: &str = "
+        ENVIRONMENT { width: 10, height: 10, steps: 4 }
+        SPECIES {
+            Walker { power: random(0, 5), routine: walk }
+            ROUTINE walk {
+                self.x = self.x + random(0, 2);
+                self.power = self.power + random(0, 1);
+            }
+        }
+        SPAWN { spawn Walker @ (1, 1); spawn Walker @ (5, 5); }
+        FITNESS { return self.power * 10 + self.x; }
+        MUTATE { mutation: { self.power = self.power + random(0, 3) - 1; } }
+        EVOLVE { generations: 8, instances: 4 }
+    ";
+
+    fn program(vm: bool) -> Arc<Program> {
+        let (tokens, lex_errors) = lex(SOURCE);
+        let (mut program, parse_errors) = Parser::new(tokens).parse_program();
+        assert!(!has_errors(&lex_errors) && !has_errors(&parse_errors));
+        assert!(!has_errors(&validate_program(&program)));
+        program.evolve_block.seed = Some(11);
+        if vm {
+            program.bytecode = Some(Arc::new(compile(&program)));
+        }
+        Arc::new(program)
+    }
+
+    fn run(sim: &mut Simulation, generations: i32) -> Vec<(i32, i32, i32)> {
+        sim.record_steps = false;
+        (0..generations)
+            .map(|_| sim.run_generation().unwrap())
+            .map(|r| (r.generation, r.avg_fitness, r.best_fitness))
+            .collect()
+    }
+
+    fn ids(sim: &Simulation) -> Vec<Vec<u64>> {
+        sim.instances.iter().map(|w| w.individuals.iter().map(|ind| ind.id).collect()).collect()
+    }
+
+    #[test]
+    fn resume_matches_the_uninterrupted_run() {
+        for vm in [false, true] {
+            let path = std::env::temp_dir().join(format!("simulanka-checkpoint-test-{}-{}", std::process::id(), vm));
+            let path = path.to_str().unwrap();
+
+            let mut full = Simulation::new(program(vm));
+            let mut first = run(&mut full, 3);
+            save(&full, path).unwrap();
+            let saved_ids = ids(&full);
+            first.extend(run(&mut full, 5));
+
+            let mut resumed = Simulation::without_worlds(program(vm));
+            let checkpoint = Checkpoint::load(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(checkpoint.seed, 11);
+            checkpoint.restore(&mut resumed).unwrap();
+            assert_eq!(ids(&resumed), saved_ids);
+
+            let rest = run(&mut resumed, 5);
+            assert_eq!(rest, first[3..], "vm: {}", vm);
+            assert_eq!(resumed.global_best_fitness, full.global_best_fitness);
+            let fitness = |sim: &Simulation| sim.instances.iter().map(|w| w.fitness).collect::<Vec<_>>();
+            assert_eq!(fitness(&resumed), fitness(&full));
+        }
+    }
+}
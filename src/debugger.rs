@@ -0,0 +1,251 @@
+//debugger.rs - step through DSL code from a terminal prompt (--debug)
+//runs a single world on the main thread so output stays in order. every
+//command checks in here before it runs (Command::execute, only when
+//program.debug is set), and the debugger stops when a breakpoint line is
+//reached or when stepping.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+use std::sync::{Arc, RwLock};
+
+use crate::types::*;
+use crate::eval::{lookup_var, read_field};
+
+const HELP: &str = "Commands:
+  s, step         run the next command (also: empty line)
+  c, continue     run until the next breakpoint
+  b N             set a breakpoint on line N
+  d N             delete the breakpoint on line N
+  bl              list breakpoints
+  l, locals       variables of the current scope
+  self            properties of self
+  p PATH          print a variable, e.g. p energy, p target.genes, p genes[0]
+  q, quit         stop debugging and let the run finish
+  h, help         this text";
+
+thread_local! {
+    //only set on the thread running the debug world
+    static DEBUGGER: RefCell<Option<Debugger>> = const { RefCell::new(None) };
+}
+
+pub struct Debugger {
+    source: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    stepping: bool, //stop before the next command
+    stopped_in: Option<(*const Command, usize)>, //command that hit a breakpoint and is still running, with its line
+}
+
+//called by Command::execute before every command
+pub fn before(cmd: &Command, env: &Arc<RwLock<Environment>>) {
+    DEBUGGER.with(|d| {
+        if let Some(debugger) = d.borrow_mut().as_mut() {
+            debugger.before(cmd, env);
+        }
+    });
+}
+
+//called by Command::execute once the command is done
+pub fn after(cmd: &Command) {
+    DEBUGGER.with(|d| {
+        if let Some(debugger) = d.borrow_mut().as_mut()
+            && debugger.stopped_in.is_some_and(|(c, _)| std::ptr::eq(c, cmd)) {
+            debugger.stopped_in = None;
+        }
+    });
+}
+
+impl Debugger {
+    fn before(&mut self, cmd: &Command, env: &Arc<RwLock<Environment>>) {
+        //a breakpoint fires every time a command on its line starts, except for
+        //commands nested in one that already stopped there (if x { y } on one line)
+        let line = cmd.line();
+        let nested = self.stopped_in.is_some_and(|(_, l)| l == line);
+        let at_breakpoint = !nested && self.breakpoints.contains(&line);
+        if at_breakpoint {
+            self.stopped_in = Some((cmd, line));
+        }
+        if !self.stepping && !at_breakpoint {
+            return;
+        }
+
+        self.show_location(line, env);
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            io::stdout().flush().ok();
+            let mut input = String::new();
+            //end of input: nobody is there to answer, just finish the run
+            if stdin.lock().read_line(&mut input).unwrap_or(0) == 0 {
+                println!();
+                self.detach();
+                return;
+            }
+            let mut words = input.split_whitespace();
+            let command = words.next().unwrap_or("s");
+            let arg = words.next();
+            match command {
+                "s" | "step" => {
+                    self.stepping = true;
+                    return;
+                }
+                "c" | "continue" => {
+                    self.stepping = false;
+                    return;
+                }
+                "q" | "quit" => {
+                    self.detach();
+                    return;
+                }
+                "b" | "d" => match arg.and_then(|a| a.parse::<usize>().ok()) {
+                    Some(n) if command == "b" => {
+                        self.breakpoints.insert(n);
+                        println!("Breakpoint on line {}", n);
+                    }
+                    Some(n) => {
+                        if self.breakpoints.remove(&n) {
+                            println!("Deleted breakpoint on line {}", n);
+                        } else {
+                            println!("No breakpoint on line {}", n);
+                        }
+                    }
+                    None => println!("{} needs a line number", command),
+                },
+                "bl" => {
+                    if self.breakpoints.is_empty() {
+                        println!("No breakpoints");
+                    }
+                    for n in &self.breakpoints {
+                        println!("  line {}: {}", n, self.source_line(*n));
+                    }
+                }
+                "l" | "locals" => print_store(&env.read().unwrap()),
+                "self" => match lookup_var(env, "self") {
+                    Value::Object(obj) => print_store(&obj.read().unwrap()),
+                    _ => println!("No self here"),
+                },
+                "p" | "print" => match arg {
+                    Some(path) => match resolve(path, env) {
+                        Ok(Value::Object(obj)) => print_store(&obj.read().unwrap()),
+                        Ok(value) => println!("{} = {}", path, value.describe()),
+                        Err(e) => println!("{}", e),
+                    },
+                    None => println!("p needs a variable, e.g. p energy"),
+                },
+                "h" | "help" => println!("{}", HELP),
+                other => println!("Unknown command '{}' (h for help)", other),
+            }
+        }
+    }
+
+    fn detach(&mut self) {
+        self.stepping = false;
+        self.breakpoints.clear();
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        self.source.get(line.wrapping_sub(1)).map_or("", |s| s.trim())
+    }
+
+    //where execution stopped: line, who is acting and the code
+    fn show_location(&self, line: usize, env: &Arc<RwLock<Environment>>) {
+        let who = match lookup_var(env, "self") {
+            Value::Object(obj) => {
+                let obj = obj.read().unwrap();
+                let species = obj.store.get("species").map_or("?".to_string(), |v| v.to_string());
+                let x = obj.store.get("x").map_or("?".to_string(), |v| v.to_string());
+                let y = obj.store.get("y").map_or("?".to_string(), |v| v.to_string());
+                format!(" {} at ({}, {})", species, x, y)
+            }
+            _ => String::new(),
+        };
+        println!("line {}{}: {}", line, who, self.source_line(line));
+    }
+}
+
+//variable, field and index chain like target.genes[0].
+//checked by hand so looking at something can't raise a strict mode error
+fn resolve(path: &str, env: &Arc<RwLock<Environment>>) -> Result<Value, String> {
+    let end = path.find(['.', '[']).unwrap_or(path.len());
+    let mut value = lookup_var(env, &path[..end]);
+    let mut rest = &path[end..];
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if !matches!(value, Value::Object(_)) {
+                return Err(format!("'{}' is not an object", &path[..path.len() - rest.len()]));
+            }
+            value = read_field(value, &after[..end], 0);
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let close = after.find(']').ok_or("missing ]")?;
+            let idx = after[..close].trim().parse::<usize>().map_err(|_| format!("bad index '{}'", &after[..close]))?;
+            value = match value {
+                Value::List(list) => list.read().unwrap().get(idx).cloned().ok_or(format!("index {} out of range", idx))?,
+                _ => return Err(format!("'{}' is not a list", &path[..path.len() - rest.len()])),
+            };
+            rest = &after[close + 1..];
+        } else {
+            return Err(format!("can't read '{}'", rest));
+        }
+    }
+    Ok(value)
+}
+
+//one line per variable, sorted by name
+fn print_store(env: &Environment) {
+    let mut names: Vec<&String> = env.store.keys().collect();
+    names.sort();
+    if names.is_empty() {
+        println!("  (empty)");
+    }
+    for name in names {
+        println!("  {} = {}", name, env.store[name].describe());
+    }
+    if env.dead {
+        println!("  (dead)");
+    }
+}
+
+//run one world on this thread with the debugger attached.
+//generations mutate that world in place, there is no selection or crossover
+pub fn run(program: Arc<Program>, source: &str, breakpoints: Vec<usize>) -> ExitCode {
+    let stepping = breakpoints.is_empty();
+    DEBUGGER.with(|d| {
+        *d.borrow_mut() = Some(Debugger {
+            source: source.lines().map(|l| l.to_string()).collect(),
+            breakpoints: breakpoints.into_iter().collect(),
+            stepping,
+            stopped_in: None,
+        });
+    });
+    println!("{}", HELP);
+
+    let mut world = World::new(program.clone(), 0);
+    world.spawn();
+    for g in 1..=program.evolve_block.generations {
+        if world.error.is_some() {
+            break;
+        }
+        world.generation = g;
+        for _ in 0..program.env_steps {
+            world.step();
+        }
+        let best = world.calculate_total_fitness();
+        for warning in world.warnings.drain(..) {
+            println!("{}", warning.as_warning());
+        }
+        println!("[Gen {}] Best: {}", g, best);
+        world.mutate();
+    }
+
+    DEBUGGER.with(|d| *d.borrow_mut() = None);
+    match &world.error {
+        Some(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::SUCCESS,
+    }
+}
@@ -0,0 +1,165 @@
+//diagnostic.rs - errors and warnings found before the program runs
+//the lexer, parser and semantic checks all report these. they point at a
+//place in the source, so they can be printed rustc-style with the offending
+//line underlined, or drawn as underlines in the gui editor.
+
+use std::fmt;
+
+use crate::types::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,   //the program can't run
+    Warning, //it runs, but probably not as intended
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,             //0 when it isn't about one place (a missing block)
+    pub col: usize,              //first column, 0 marks the whole line
+    pub end_col: usize,          //column after the last underlined character
+    pub context: Option<String>, //block it was found in: "ENVIRONMENT", "ROUTINE move", ...
+    pub notes: Vec<String>,      //hints printed under the source line
+}
+
+impl Diagnostic {
+    pub fn error(message: &str) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.to_string(),
+            line: 0,
+            col: 0,
+            end_col: 0,
+            context: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: &str) -> Self {
+        let mut d = Diagnostic::error(message);
+        d.severity = Severity::Warning;
+        d
+    }
+
+    //columns col..end_col on `line`
+    pub fn at(mut self, line: usize, col: usize, end_col: usize) -> Self {
+        self.line = line;
+        self.col = col;
+        self.end_col = end_col.max(col + 1);
+        self
+    }
+
+    //the whole of `line`
+    pub fn on_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self.col = 0;
+        self.end_col = 0;
+        self
+    }
+
+    pub fn span(self, span: Span) -> Self {
+        self.at(span.line, span.col, span.end_col)
+    }
+
+    pub fn context(mut self, context: &str) -> Self {
+        self.context = Some(context.to_string());
+        self
+    }
+
+    pub fn note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    //the report with the source line and a caret underline:
+    //
+    //  error: Undefined variable: enrgy
+    //   --> wolves.txt:12:9 (ROUTINE hunt)
+    //     |
+    //  12 |     x = enrgy + 1;
+    //     |         ^^^^^
+    //     = note: ...
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut out = format!("{}: {}", self.severity, self.message);
+        let context = match &self.context {
+            Some(c) => format!(" ({})", c),
+            None => String::new(),
+        };
+
+        let text = if self.line > 0 { source.lines().nth(self.line - 1) } else { None };
+        let Some(text) = text else {
+            if self.line > 0 {
+                out.push_str(&format!("\n --> {}:{}{}", path, self.line, context));
+            } else if !context.is_empty() {
+                out.push_str(&format!("\n --> {}{}", path, context));
+            }
+            for note in &self.notes {
+                out.push_str(&format!("\n = note: {}", note));
+            }
+            return out;
+        };
+
+        let gutter = " ".repeat(self.line.to_string().len());
+        let location = if self.col > 0 { format!("{}:{}", self.line, self.col) } else { self.line.to_string() };
+        out.push_str(&format!("\n{}--> {}:{}{}", gutter, path, location, context));
+        out.push_str(&format!("\n{} |", gutter));
+        out.push_str(&format!("\n{} | {}", self.line, text));
+
+        //without a column the underline covers the line's code
+        let chars: Vec<char> = text.chars().collect();
+        let (start, end) = if self.col > 0 {
+            (self.col - 1, self.end_col.saturating_sub(1).max(self.col))
+        } else {
+            let first = chars.iter().position(|c| !c.is_whitespace()).unwrap_or(0);
+            let last = chars.iter().rposition(|c| !c.is_whitespace()).map_or(first + 1, |p| p + 1);
+            (first, last)
+        };
+        //keep tabs so the carets line up under the text
+        let mut pad = String::new();
+        for c in chars.iter().take(start) {
+            pad.push(if *c == '\t' { '\t' } else { ' ' });
+        }
+        out.push_str(&format!("\n{} | {}{}", gutter, pad, "^".repeat(end.saturating_sub(start).max(1))));
+        for note in &self.notes {
+            out.push_str(&format!("\n{} = note: {}", gutter, note));
+        }
+        out
+    }
+}
+
+//one line form for logs and the gui: "error at line 3:5 in ENVIRONMENT: Expected Key"
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if self.line > 0 {
+            write!(f, " at line {}", self.line)?;
+            if self.col > 0 {
+                write!(f, ":{}", self.col)?;
+            }
+        }
+        if let Some(c) = &self.context {
+            write!(f, " in {}", c)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+//true if any of them stops the program from running
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.is_error())
+}
@@ -0,0 +1,170 @@
+//editor.rs - source editor shown in the gui
+//highlighting follows the lexer's tokens, and diagnostics are underlined
+//where they point: red for errors, yellow for warnings.
+
+use eframe::egui;
+use egui::text::{LayoutJob, TextFormat};
+
+use crate::lexer::{lexer, TokenKind};
+use crate::reload::check_source;
+use crate::diagnostic::{Diagnostic, Severity};
+
+pub struct Editor {
+    pub open: bool,
+    pub text: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor { open: false, text: String::new(), diagnostics: Vec::new() }
+    }
+
+    //start editing `source`
+    pub fn load(&mut self, source: &str) {
+        self.text = source.to_string();
+        self.check();
+    }
+
+    //re-run the checks, called after every edit
+    pub fn check(&mut self) {
+        self.diagnostics = match check_source(&self.text) {
+            Ok((_, warnings)) => warnings,
+            Err(diagnostics) => diagnostics,
+        };
+    }
+
+    //the highlighted text box. returns true if the text was edited
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let diagnostics = &self.diagnostics;
+        let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+            let mut job = highlight(text, diagnostics);
+            job.wrap.max_width = wrap_width;
+            ui.fonts(|f| f.layout_job(job))
+        };
+        let response = ui.add(
+            egui::TextEdit::multiline(&mut self.text)
+                .code_editor()
+                .desired_width(f32::INFINITY)
+                .desired_rows(30)
+                .layouter(&mut layouter),
+        );
+        response.changed()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Plain,
+    Block,   //ENVIRONMENT, SPECIES, ... headers
+    Keyword, //if, while, spawn, ...
+    Number,
+    Text,    //string literals
+    Comment,
+    Symbol,
+}
+
+impl Style {
+    fn of(kind: &TokenKind) -> Self {
+        match kind {
+            TokenKind::Environment | TokenKind::Species | TokenKind::Evolve | TokenKind::Mutate
+            | TokenKind::Fitness | TokenKind::Visualize | TokenKind::Routine | TokenKind::Function => Style::Block,
+            TokenKind::Spawn | TokenKind::Die | TokenKind::At | TokenKind::Random | TokenKind::If
+            | TokenKind::Else | TokenKind::While | TokenKind::For | TokenKind::In | TokenKind::Return
+            | TokenKind::Print | TokenKind::True | TokenKind::False => Style::Keyword,
+            TokenKind::Number(_) | TokenKind::WideNumber(_) | TokenKind::Float(_) => Style::Number,
+            TokenKind::StringLiteral(_) => Style::Text,
+            TokenKind::Identifier(_) => Style::Plain,
+            _ => Style::Symbol,
+        }
+    }
+
+    fn color(self) -> egui::Color32 {
+        match self {
+            Style::Plain => egui::Color32::from_rgb(220, 220, 220),
+            Style::Block => egui::Color32::from_rgb(240, 170, 80),
+            Style::Keyword => egui::Color32::from_rgb(200, 140, 240),
+            Style::Number => egui::Color32::from_rgb(130, 200, 250),
+            Style::Text => egui::Color32::from_rgb(150, 210, 120),
+            Style::Comment => egui::Color32::from_rgb(120, 120, 120),
+            Style::Symbol => egui::Color32::from_rgb(180, 180, 180),
+        }
+    }
+}
+
+//colors for every character, then one text section per run of the same look
+pub fn highlight(text: &str, diagnostics: &[Diagnostic]) -> LayoutJob {
+    let chars: Vec<char> = text.chars().collect();
+    let mut line_starts = vec![0];
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '\n' {
+            line_starts.push(i + 1);
+        }
+    }
+    let offset = |line: usize, col: usize| -> Option<usize> {
+        line_starts.get(line.checked_sub(1)?).map(|start| start + col.saturating_sub(1))
+    };
+
+    let mut styles = vec![Style::Plain; chars.len()];
+    let mut covered = vec![false; chars.len()];
+    for token in lexer(text) {
+        let Some(start) = offset(token.line, token.col) else { continue };
+        let len = token.end_col.saturating_sub(token.col);
+        for i in start..(start + len).min(chars.len()) {
+            styles[i] = Style::of(&token.kind);
+            covered[i] = true;
+        }
+    }
+
+    //comments are skipped by the lexer: a // outside of any token
+    let mut i = 0;
+    while i + 1 < chars.len() {
+        if !covered[i] && chars[i] == '/' && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' {
+                styles[i] = Style::Comment;
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+
+    //underline the diagnostic's columns, or the whole line without a column.
+    //errors win over warnings on the same characters
+    let mut underlined = vec![None; chars.len()];
+    for d in diagnostics {
+        let located = if d.col > 0 { offset(d.line, d.col) } else { None };
+        let (start, end) = match located {
+            Some(start) => (start, start + d.end_col.saturating_sub(d.col).max(1)),
+            None => match offset(d.line, 1) {
+                Some(start) => {
+                    let end = chars[start..].iter().position(|&c| c == '\n').map_or(chars.len(), |n| start + n);
+                    (start, end)
+                }
+                None => continue,
+            },
+        };
+        for u in underlined.iter_mut().take(end.min(chars.len())).skip(start) {
+            if *u != Some(Severity::Error) {
+                *u = Some(d.severity);
+            }
+        }
+    }
+
+    let mut job = LayoutJob::default();
+    let font = egui::FontId::monospace(13.0);
+    let mut run = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        run.push(*c);
+        let last = i + 1 == chars.len();
+        if last || styles[i + 1] != styles[i] || underlined[i + 1] != underlined[i] {
+            let underline = match underlined[i] {
+                Some(Severity::Error) => egui::Stroke::new(1.5, egui::Color32::RED),
+                Some(Severity::Warning) => egui::Stroke::new(1.5, egui::Color32::YELLOW),
+                None => egui::Stroke::NONE,
+            };
+            job.append(&run, 0.0, TextFormat { font_id: font.clone(), color: styles[i].color(), underline, ..Default::default() });
+            run.clear();
+        }
+    }
+    job
+}
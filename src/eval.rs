@@ -1,32 +1,330 @@
 //eval.rs - evaluates expressions and commands
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock}; //arc is really really really important - multithreading
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
 use crate::types::*;
+use crate::spatial::{Metric, SpatialIndex, position};
+use crate::debugger;
 
 //global state (thread_local for safety)
 
 thread_local! {
-    //individuals are shared grid is cached for each thread
-    pub static GRID_CACHE: RefCell<Option<HashMap<(i32, i32), Arc<RwLock<Environment>>>>> = RefCell::new(None);
+    //who stands on which cell, built per thread by the running world
+    #[allow(clippy::missing_const_for_thread_local)]
+    pub static GRID_CACHE: RefCell<Option<SpatialIndex>> = RefCell::new(None);
     
     //drawing commands for visualization
+    #[allow(clippy::missing_const_for_thread_local)]
     pub static DRAW_COMMANDS: RefCell<Vec<DrawCmd>> = RefCell::new(Vec::new());
     
     //current world size
+    #[allow(clippy::missing_const_for_thread_local)]
     pub static WORLD_DIMENSIONS: RefCell<(i32, i32)> = RefCell::new((100, 100));
+
+    //rng of the world running on this thread (swapped in by World::swap_thread_state)
+    pub static RNG: RefCell<ChaCha12Rng> = RefCell::new(ChaCha12Rng::seed_from_u64(0));
+
+    //program whose code runs on this thread (needed to find user FUNCTIONs)
+    pub static PROGRAM: RefCell<Option<Arc<Program>>> = const { RefCell::new(None) };
+
+    //how deep we are in nested FUNCTION calls
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+
+    //strict mode of the installed program: failures raise errors instead of giving 0
+    static STRICT: Cell<bool> = const { Cell::new(false) };
+
+    //failures outside strict mode, until the running world takes them (see World::swap_thread_state)
+    static WARNINGS: RefCell<Vec<RuntimeError>> = const { RefCell::new(Vec::new()) };
+
+    //first error raised while evaluating the current command
+    static PENDING_ERROR: RefCell<Option<RuntimeError>> = const { RefCell::new(None) };
+
+    //set during a synchronous step (see World::step)
+    pub static SYNC_BUFFERS: RefCell<Option<SyncBuffers>> = const { RefCell::new(None) };
+}
+
+//double buffer for a synchronous step. routines read copies of the previous
+//state and write into the live individuals. the individual maps are keyed by
+//env_key, the list map by list_key
+pub struct SyncBuffers {
+    pub live: HashMap<usize, Arc<RwLock<Environment>>>,     //previous copy -> live individual
+    pub previous: HashMap<usize, Arc<RwLock<Environment>>>, //live individual -> previous copy
+    pub lists: HashMap<usize, Arc<RwLock<Vec<Value>>>>,     //list in a previous copy -> live list
+    pub claimed: HashMap<(i32, i32), usize>,                //cell moved onto this step -> env_key of who did
+    pub scratch: HashMap<usize, Arc<RwLock<Environment>>>,  //env a routine runs in -> live individual it runs for
+}
+
+//identity of an environment, for maps keyed by individual
+pub fn env_key(env: &Arc<RwLock<Environment>>) -> usize {
+    Arc::as_ptr(env) as usize
+}
+
+//identity of a list, for SyncBuffers.lists
+pub fn list_key(list: &Arc<RwLock<Vec<Value>>>) -> usize {
+    Arc::as_ptr(list) as usize
+}
+
+//deepest allowed FUNCTION recursion before a call gives up
+pub const MAX_CALL_DEPTH: usize = 256;
+
+//make a program's FUNCTIONs callable from code running on this thread
+pub fn install_program(program: &Arc<Program>) {
+    PROGRAM.with(|p| {
+        let mut current = p.borrow_mut();
+        let same = matches!(current.as_ref(), Some(old) if Arc::ptr_eq(old, program));
+        if !same {
+            *current = Some(program.clone());
+        }
+    });
+    STRICT.with(|s| s.set(program.strict));
+    PENDING_ERROR.with(|e| *e.borrow_mut() = None);
+}
+
+//record a runtime error (strict mode only, otherwise the caller just falls back to 0).
+//only the first error is kept, the command checks for it once it is done evaluating
+pub fn raise(line: usize, message: String) {
+    if !STRICT.with(|s| s.get()) {
+        return;
+    }
+    PENDING_ERROR.with(|e| {
+        let mut pending = e.borrow_mut();
+        if pending.is_none() {
+            *pending = Some(RuntimeError::new(line, message));
+        }
+    });
+}
+
+//a failure that doesn't stop the run outside strict mode. it is reported
+//by the simulation after the parallel part, so workers don't print over
+//each other. the same line and message is only kept once
+pub fn warn(line: usize, message: String) {
+    WARNINGS.with(|w| {
+        let mut warnings = w.borrow_mut();
+        if !warnings.iter().any(|e| e.line == line && e.message == message) {
+            warnings.push(RuntimeError::new(line, message));
+        }
+    });
+}
+
+pub fn take_warnings() -> Vec<RuntimeError> {
+    WARNINGS.with(|w| std::mem::take(&mut *w.borrow_mut()))
+}
+
+//turn a raised error into an Err for Command::execute
+pub fn check_error() -> Result<(), RuntimeError> {
+    match PENDING_ERROR.with(|e| e.borrow_mut().take()) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+//keep the spatial index in step when x or y of an individual is assigned.
+//called before the value is stored. the occupancy policy isn't checked yet:
+//commit_moves does that once the individual's whole move is done
+pub fn track_move(obj: &Arc<RwLock<Environment>>, field: &str, value: &Value) {
+    if field != "x" && field != "y" {
+        return;
+    }
+    GRID_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let index = match cache.as_mut() {
+            Some(index) => index,
+            None => return,
+        };
+        let from = match position(&obj.read().unwrap()) {
+            Some(pos) => pos,
+            None => return,
+        };
+        let to = if field == "x" { (value.to_int(), from.1) } else { (from.0, value.to_int()) };
+        index.note_move(obj);
+
+        //synchronous step: the index holds everyone's previous position and stays that way
+        if !is_buffered(obj) {
+            index.relocate(obj, from, to);
+        }
+    })
+}
+
+//check the occupancy policy for everyone who moved since the last commit, at the
+//cell they ended up on. a blocked move is undone as a whole, so a diagonal step
+//is never half applied and a cell only passed over on the way doesn't block.
+//called after each individual's routine (and fitness)
+pub fn commit_moves() {
+    GRID_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let index = match cache.as_mut() {
+            Some(index) => index,
+            None => return,
+        };
+        for (env, x, y) in index.take_moves() {
+            let start = (x.to_int(), y.to_int());
+            let end = match position(&env.read().unwrap()) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            if !index.is_blocking() || index.wrap(start) == index.wrap(end) {
+                continue;
+            }
+
+            let buffered = is_buffered(&env);
+            let blocked = if buffered {
+                index.blocks(end) || !claim(&env, index.wrap(end))
+            } else {
+                index.taken_by_other(&env, end)
+            };
+            if blocked {
+                let mut env_mut = env.write().unwrap();
+                env_mut.store.insert("x".to_string(), x);
+                env_mut.store.insert("y".to_string(), y);
+                drop(env_mut);
+                if !buffered {
+                    index.relocate(&env, end, start);
+                }
+            }
+        }
+    })
+}
+
+//is this a live individual of a synchronous step
+fn is_buffered(obj: &Arc<RwLock<Environment>>) -> bool {
+    SYNC_BUFFERS.with(|b| {
+        b.borrow().as_ref().is_some_and(|b| b.previous.contains_key(&env_key(obj)))
+    })
+}
+
+//synchronous step: claim a cell for the individual, false if someone else
+//already moved onto it this step. moving again gives up the cell claimed before
+fn claim(obj: &Arc<RwLock<Environment>>, cell: (i32, i32)) -> bool {
+    SYNC_BUFFERS.with(|b| {
+        let mut b = b.borrow_mut();
+        let claimed = match b.as_mut() {
+            Some(b) => &mut b.claimed,
+            None => return true,
+        };
+        let me = env_key(obj);
+        if claimed.get(&cell).is_some_and(|owner| *owner != me) {
+            return false;
+        }
+        claimed.retain(|_, owner| *owner != me);
+        claimed.insert(cell, me);
+        true
+    })
+}
+
+//occupancy: block keeps a new individual off a taken cell, taken by someone
+//in the world or by an earlier spawn of the same block or step
+pub fn spawn_allowed(x: &Value, y: &Value, individuals: &[Individual], spawner: &[Individual], program: &Program) -> bool {
+    if program.env_occupancy != Occupancy::Block {
+        return true;
+    }
+    let (width, height) = WORLD_DIMENSIONS.with(|d| *d.borrow());
+    let (width, height) = (width.max(1), height.max(1));
+    let cell = (x.to_int().rem_euclid(width), y.to_int().rem_euclid(height));
+    let taken = |inds: &[Individual]| {
+        inds.iter().any(|ind| {
+            let env_b = ind.env.read().unwrap();
+            !env_b.dead && position(&env_b).map(|(px, py)| (px.rem_euclid(width), py.rem_euclid(height))) == Some(cell)
+        })
+    };
+    let indexed = GRID_CACHE.with(|cache| {
+        cache.borrow().as_ref().map(|index| index.first_alive_in_cell(cell.0, cell.1).is_some())
+    });
+    let in_world = match indexed {
+        Some(found) => found,
+        None => taken(individuals),
+    };
+    !in_world && !taken(spawner)
+}
+
+//where a write to an individual goes. in a synchronous step the objects code
+//can see are copies of the previous state, their writes land on the live individual
+pub fn write_target(obj: Arc<RwLock<Environment>>) -> Arc<RwLock<Environment>> {
+    let live = SYNC_BUFFERS.with(|b| {
+        b.borrow().as_ref().and_then(|b| b.live.get(&env_key(&obj)).cloned())
+    });
+    live.unwrap_or(obj)
+}
+
+//the live individual a synchronous routine's scratch env `env` runs for
+pub fn scratch_owner(env: &Arc<RwLock<Environment>>) -> Option<Arc<RwLock<Environment>>> {
+    SYNC_BUFFERS.with(|b| {
+        b.borrow().as_ref().and_then(|b| b.scratch.get(&env_key(env)).cloned())
+    })
+}
+
+//bare variable assignment. a routine in a synchronous step runs in a scratch env
+//that reads through to the previous state, what it sets also lands on the live individual
+pub fn set_var(env: &Arc<RwLock<Environment>>, name: &str, value: Value) {
+    match scratch_owner(env) {
+        Some(live) => {
+            track_move(&live, name, &value);
+            live.write().unwrap().store.insert(name.to_string(), value.clone());
+        }
+        None => track_move(env, name, &value),
+    }
+    env.write().unwrap().store.insert(name.to_string(), value);
+}
+
+//the same for lists: a list read from a previous-state copy is changed
+//(genes[i] = v, push, pop) in the live individual's list
+pub fn write_list(list: Arc<RwLock<Vec<Value>>>) -> Arc<RwLock<Vec<Value>>> {
+    let live = SYNC_BUFFERS.with(|b| {
+        b.borrow().as_ref().and_then(|b| b.lists.get(&list_key(&list)).cloned())
+    });
+    live.unwrap_or(list)
+}
+
+//individuals around self for the neighborhood builtins, nearest first, self left out.
+//uses the world's spatial index, or a throwaway one when none is built (e.g. in SPAWN)
+fn neighborhood(
+    env: &Arc<RwLock<Environment>>,
+    radius: i32,
+    metric: Metric,
+    individuals: &[Individual],
+) -> Vec<(i32, Arc<RwLock<Environment>>)> {
+    let me = match env.read().unwrap().store.get("self") {
+        Some(Value::Object(me)) => me.clone(),
+        _ => return Vec::new(),
+    };
+    let center = match position(&me.read().unwrap()) {
+        Some(pos) => pos,
+        None => return Vec::new(),
+    };
+    let dims = WORLD_DIMENSIONS.with(|d| *d.borrow());
+
+    let cached = GRID_CACHE.with(|cache| {
+        cache.borrow().as_ref().map(|index| index.within(center, radius, metric))
+    });
+    let mut found = match cached {
+        Some(found) => found,
+        None => SpatialIndex::build(individuals, Occupancy::Stack, dims).within(center, radius, metric),
+    };
+    //in a synchronous step self is the previous-state copy
+    let me = write_target(me);
+    found.retain(|(_, other)| !Arc::ptr_eq(&write_target(other.clone()), &me));
+    found
+}
+
+//does an individual belong to the species
+fn is_species(env: &Arc<RwLock<Environment>>, species: &str) -> bool {
+    matches!(env.read().unwrap().store.get("species"), Some(Value::String(s)) if s == species)
 }
 
 //exp evaluation
 
 impl Exp {
     //convert an expression to an integer
+    #[allow(clippy::collapsible_match)]
     pub fn eval(&self, env: Arc<RwLock<Environment>>, individuals: &[Individual]) -> i32 {
         match self {
             //simple values
             Exp::Int(v, _l) => *v,
+            Exp::Float(f, _l) => *f as i32,
             Exp::Bool(b, _l) => if *b { 1 } else { 0 },
             
             //variable lookup - check local first then self
@@ -44,42 +342,25 @@ impl Exp {
             }
             
             //field access: self.x, target.speed
-            Exp::Dot(obj, field, _l) => {
-                let obj_val = obj.eval_to_val(env.clone(), individuals);
-                if let Value::Object(obj_env) = obj_val {
-                    obj_env.read().unwrap().store.get(field).map_or(0, |v| v.to_int())
-                } else {
-                    0
-                }
-            }
+            Exp::Dot(..) => self.eval_to_val(env, individuals).to_int(),
             
             //math operations: a + b, x * y
-            Exp::BinaryOp(left, op, right, _l) => {
-                let left_val = left.eval(env.clone(), individuals);
-                let right_val = right.eval(env, individuals);
-                
-                match op.as_str() {
-                    "+" => left_val + right_val,
-                    "-" => left_val - right_val,
-                    "*" => left_val * right_val,
-                    "/" => if right_val != 0 { left_val / right_val } else { 0 },
-                    "%" => if right_val != 0 { left_val % right_val } else { 0 },
-                    _ => 0,
-                }
+            Exp::BinaryOp(left, op, right, span) => {
+                let left_val = left.eval_to_val(env.clone(), individuals);
+                let right_val = right.eval_to_val(env, individuals);
+                binary_op(&left_val, op, &right_val, span.line).to_int()
             }
             
             //function calls: random(0, 10)
             Exp::Call(name, args, _l) => { //_l is not used so _
                 //handle random separately since its used the most and doesnt depend on other objects
-                if name == "random" && args.len() == 2 {
-                    use rand::Rng;
-                    let min = args[0].eval(env.clone(), individuals);
-                    let max = args[1].eval(env, individuals);
-                    if max > min {
-                        rand::thread_rng().gen_range(min..max)
-                    } else {
-                        min
+                if name == "random" {
+                    if args.len() != 2 {
+                        return 0;
                     }
+                    let min = args[0].eval_to_val(env.clone(), individuals);
+                    let max = args[1].eval_to_val(env, individuals);
+                    random_between(&min, &max).to_int()
                 } else {
                     //for other calls get full value and convert to int
                     self.eval_to_val(env, individuals).to_int()
@@ -87,57 +368,33 @@ impl Exp {
             }
             
             //array access: genes[i]
-            Exp::Index(list_exp, idx_exp, _l) => {
-                let list_val = list_exp.eval_to_val(env.clone(), individuals);
-                let idx = idx_exp.eval(env, individuals) as usize;
-                
-                if let Value::List(list) = list_val {
-                    let borrowed = list.read().unwrap();
-                    if idx < borrowed.len() {
-                        borrowed[idx].to_int()
-                    } else {
-                        0
-                    }
-                } else {
-                    0
-                }
-            }
+            Exp::Index(..) => self.eval_to_val(env, individuals).to_int(),
             
             _ => 0,
         }
     }
 
+    //get the value of an expression as a float (for coordinates)
+    pub fn eval_float(&self, env: Arc<RwLock<Environment>>, individuals: &[Individual]) -> f64 {
+        self.eval_to_val(env, individuals).to_float()
+    }
+
     //get the full value of an expression (keeps strings lists etc)
     pub fn eval_to_val(&self, env: Arc<RwLock<Environment>>, individuals: &[Individual]) -> Value {
         match self {
             //literals
             Exp::Int(v, _l) => Value::Int(*v),
+            Exp::Float(f, _l) => Value::Float(*f),
             Exp::Bool(b, _l) => Value::Bool(*b),
             Exp::StringLiteral(s, _l) => Value::String(s.clone()),
             
             //variable lookup - high speed: flat access
-            Exp::Var(name, _l) => {
-                let env_ref = env.read().unwrap();
-                //check local/creature store
-                if let Some(v) = env_ref.store.get(name) {
-                    return v.clone();
-                }
-                
-                if name == "environment" {
-                    return Value::Environment;
-                }
-                
-                Value::Int(0)
-            }
+            Exp::Var(name, _l) => lookup_var(&env, name),
             
             //field access: self.species, target.x
-            Exp::Dot(obj, field, _l) => {
+            Exp::Dot(obj, field, span) => {
                 let obj_val = obj.eval_to_val(env.clone(), individuals);
-                if let Value::Object(obj_env) = obj_val {
-                    obj_env.read().unwrap().store.get(field).cloned().unwrap_or(Value::Int(0))
-                } else {
-                    Value::Int(0)
-                }
+                read_field(obj_val, field, span.line)
             }
             
             //list literal: [1, 2, 3]
@@ -150,77 +407,33 @@ impl Exp {
             }
             
             //array/grid access
-            Exp::Index(list_exp, idx_exp, _l) => {
+            Exp::Index(list_exp, idx_exp, span) => {
                 let list_val = list_exp.eval_to_val(env.clone(), individuals);
-                let idx = idx_exp.eval(env.clone(), individuals);
-                
-                match list_val {
-                    //normal list access: my_list[i]
-                    Value::List(list) => {
-                        let borrowed = list.read().unwrap();
-                        let i = idx as usize;
-                        if i < borrowed.len() {
-                            borrowed[i].clone()
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    //grid access: environment[x]
-                    Value::Environment => Value::GridRow(idx),
-                    //grid cell access: environment[x][y]
-                    Value::GridRow(x) => {
-                        let y = idx;
-                        
-                        //get world size for wrapping
-                        let (width, height) = WORLD_DIMENSIONS.with(|d| *d.borrow());
-                        let wrapped_x = ((x % width) + width) % width;
-                        let wrapped_y = ((y % height) + height) % height;
-
-                        //try cache first (faster)
-                        let cached = GRID_CACHE.with(|cache| {
-                            if let Option::Some(map) = cache.borrow().as_ref() {
-                                map.get(&(wrapped_x, wrapped_y)).cloned()
-                            } else {
-                                None
-                            }
-                        });
-                        
-                        if let Some(found) = cached {
-                            return Value::Object(found);
-                        }
-
-                        //search through individuals
-                        for ind in individuals {
-                            let env_b = ind.env.read().unwrap();
-                            let store = &env_b.store;
-                            let ind_x = store.get("x").map_or(0, |v| v.to_int());
-                            let ind_y = store.get("y").map_or(0, |v| v.to_int());
-                            if (ind_x % width + width) % width == wrapped_x && 
-                               (ind_y % height + height) % height == wrapped_y {
-                                return Value::Object(ind.env.clone());
-                            }
-                        }
-                        Value::Int(0)
-                    }
-                    _ => Value::Int(0)
-                }
+                let idx = idx_exp.eval(env, individuals);
+                index_value(list_val, idx, span.line, individuals)
             }
             
             //function calls
-            Exp::Call(name, args, _l) => {
-                self.run_builtin(name, args, env, individuals)
+            Exp::Call(name, args, span) => {
+                self.run_builtin(name, args, span.line, env, individuals)
             }
             
-            //for anything else, convert to int
-            Exp::BinaryOp(_, _, _, _l) => Value::Int(self.eval(env, individuals)),
+            //math keeps floats if either side is a float
+            Exp::BinaryOp(left, op, right, span) => {
+                let left_val = left.eval_to_val(env.clone(), individuals);
+                let right_val = right.eval_to_val(env, individuals);
+                binary_op(&left_val, op, &right_val, span.line)
+            }
         }
     }
 
     //run a built-in function
+    #[allow(clippy::collapsible_if, clippy::len_zero)]
     fn run_builtin(
         &self,
         name: &str,
         args: &[Exp],
+        line: usize,
         env: Arc<RwLock<Environment>>,
         individuals: &[Individual],
     ) -> Value {
@@ -240,7 +453,7 @@ impl Exp {
                 if args.len() >= 2 {
                     if let Value::List(list) = args[0].eval_to_val(env.clone(), individuals) {
                         let value = args[1].eval_to_val(env, individuals);
-                        list.write().unwrap().push(value);
+                        write_list(list).write().unwrap().push(value);
                     }
                 }
                 Value::Int(0)
@@ -250,62 +463,77 @@ impl Exp {
             "pop" => {
                 if args.len() >= 1 {
                     if let Value::List(list) = args[0].eval_to_val(env, individuals) {
-                        return list.write().unwrap().pop().unwrap_or(Value::Int(0));
+                        return write_list(list).write().unwrap().pop().unwrap_or(Value::Int(0));
                     }
                 }
                 Value::Int(0)
             }
             
-                    //get_at(nx, ny)
-                    "get_at" => {
-                        if args.len() >= 2 {
-                            let x = args[0].eval(env.clone(), individuals);
-                            let y = args[1].eval(env, individuals);
-                            
-                            //Try cache first
-                            let cached = GRID_CACHE.with(|cache| {
-                                if let Some(map) = cache.borrow().as_ref() {
-                                    map.get(&(x, y)).cloned()
-                                } else {
-                                    None
-                                }
-                            });
-                            
-                            if let Some(found) = cached {
-                                return Value::Object(found);
-                            }
+            //get_at(nx, ny)
+            "get_at" => {
+                if args.len() >= 2 {
+                    let x = args[0].eval(env.clone(), individuals);
+                    let y = args[1].eval(env, individuals);
+                    return get_at(x, y, individuals);
+                }
+                Value::Int(0)
+            }
 
-                            for ind in individuals {
-                                let env_b = ind.env.read().unwrap();
-                                let store = &env_b.store;
-                                let ind_x = store.get("x").map_or(0, |v| v.to_int());
-                                let ind_y = store.get("y").map_or(0, |v| v.to_int());
-                                if ind_x == x && ind_y == y {
-                                    return Value::Object(ind.env.clone());
-                                }
-                            }
-                        }
-                        Value::Int(0)
-                    }
+            //all_at(x, y) - list of everyone standing on a cell
+            "all_at" => {
+                if args.len() >= 2 {
+                    let x = args[0].eval(env.clone(), individuals);
+                    let y = args[1].eval(env, individuals);
+                    return all_at(x, y, individuals);
+                }
+                Value::List(Arc::new(RwLock::new(Vec::new())))
+            }
+
+            //nearest(species, radius) - closest individual of a species, 0 if none in range
+            "nearest" => {
+                if args.len() >= 2 {
+                    let species = args[0].eval_to_val(env.clone(), individuals).to_string();
+                    let radius = args[1].eval(env.clone(), individuals);
+                    return nearest(&env, &species, radius, individuals);
+                }
+                Value::Int(0)
+            }
+
+            //count_in_radius(species, radius) - how many of a species are around
+            "count_in_radius" => {
+                if args.len() >= 2 {
+                    let species = args[0].eval_to_val(env.clone(), individuals).to_string();
+                    let radius = args[1].eval(env.clone(), individuals);
+                    return count_in_radius(&env, &species, radius, individuals);
+                }
+                Value::Int(0)
+            }
+
+            //neighbors(radius) / von_neumann(radius) - everyone within manhattan distance
+            //moore(radius) - everyone in the surrounding square
+            "neighbors" | "von_neumann" | "moore" => {
+                if args.len() >= 1 {
+                    let radius = args[0].eval(env.clone(), individuals);
+                    let metric = if name == "moore" { Metric::Chebyshev } else { Metric::Manhattan };
+                    return neighbors(&env, radius, metric, individuals);
+                }
+                Value::List(Arc::new(RwLock::new(Vec::new())))
+            }
+
+            //kill(obj) - mark another individual for removal
+            "kill" => {
+                if args.len() >= 1 {
+                    kill(args[0].eval_to_val(env, individuals));
+                }
+                Value::Int(0)
+            }
 
             //dist(obj1, obj2) - distance between two objects
             "dist" => {
                 if args.len() >= 2 {
                     let obj1 = args[0].eval_to_val(env.clone(), individuals);
                     let obj2 = args[1].eval_to_val(env, individuals);
-                    
-                    if let (Value::Object(o1), Value::Object(o2)) = (obj1, obj2) {
-                    let x1 = o1.read().unwrap().store.get("x").map_or(0, |v| v.to_int());
-                    let y1 = o1.read().unwrap().store.get("y").map_or(0, |v| v.to_int());
-                    let x2 = o2.read().unwrap().store.get("x").map_or(0, |v| v.to_int());
-                    let y2 = o2.read().unwrap().store.get("y").map_or(0, |v| v.to_int());
-                        
-                        let dx = (x1 - x2) as f64;
-                        let dy = (y1 - y2) as f64;
-                        let distance = (dx * dx + dy * dy).sqrt();
-                        
-                        return Value::Int(distance as i32);
-                    }
+                    return dist(&obj1, &obj2);
                 }
                 Value::Int(0)
             }
@@ -313,10 +541,10 @@ impl Exp {
             //draw_rect(x, y, w, h, r, g, b)
             "draw_rect" => {
                 if args.len() >= 4 {
-                    let x = args[0].eval(env.clone(), individuals) as f32;
-                    let y = args[1].eval(env.clone(), individuals) as f32;
-                    let w = args[2].eval(env.clone(), individuals) as f32;
-                    let h = args[3].eval(env.clone(), individuals) as f32;
+                    let x = args[0].eval_float(env.clone(), individuals) as f32;
+                    let y = args[1].eval_float(env.clone(), individuals) as f32;
+                    let w = args[2].eval_float(env.clone(), individuals) as f32;
+                    let h = args[3].eval_float(env.clone(), individuals) as f32;
                     
                     //colors are optional, default to white
                     let r = if args.len() > 4 { args[4].eval(env.clone(), individuals) as u8 } else { 255 };
@@ -333,15 +561,15 @@ impl Exp {
             //draw_line(x1, y1, x2, y2, r, g, b, thickness)
             "draw_line" => {
                 if args.len() >= 4 {
-                    let x1 = args[0].eval(env.clone(), individuals) as f32;
-                    let y1 = args[1].eval(env.clone(), individuals) as f32;
-                    let x2 = args[2].eval(env.clone(), individuals) as f32;
-                    let y2 = args[3].eval(env.clone(), individuals) as f32;
+                    let x1 = args[0].eval_float(env.clone(), individuals) as f32;
+                    let y1 = args[1].eval_float(env.clone(), individuals) as f32;
+                    let x2 = args[2].eval_float(env.clone(), individuals) as f32;
+                    let y2 = args[3].eval_float(env.clone(), individuals) as f32;
                     
                     let r = if args.len() > 4 { args[4].eval(env.clone(), individuals) as u8 } else { 255 };
                     let g = if args.len() > 5 { args[5].eval(env.clone(), individuals) as u8 } else { 255 };
                     let b = if args.len() > 6 { args[6].eval(env.clone(), individuals) as u8 } else { 255 };
-                    let thickness = if args.len() > 7 { args[7].eval(env, individuals) as f32 } else { 1.0 };
+                    let thickness = if args.len() > 7 { args[7].eval_float(env, individuals) as f32 } else { 1.0 };
                     
                     DRAW_COMMANDS.with(|cmds| {
                         cmds.borrow_mut().push(DrawCmd::Line { x1, y1, x2, y2, r, g, b, thickness });
@@ -353,9 +581,9 @@ impl Exp {
             //draw_circle(x, y, radius, r, g, b)
             "draw_circle" => {
                 if args.len() >= 3 {
-                    let x = args[0].eval(env.clone(), individuals) as f32;
-                    let y = args[1].eval(env.clone(), individuals) as f32;
-                    let radius = args[2].eval(env.clone(), individuals) as f32;
+                    let x = args[0].eval_float(env.clone(), individuals) as f32;
+                    let y = args[1].eval_float(env.clone(), individuals) as f32;
+                    let radius = args[2].eval_float(env.clone(), individuals) as f32;
                     
                     let r = if args.len() > 3 { args[3].eval(env.clone(), individuals) as u8 } else { 255 };
                     let g = if args.len() > 4 { args[4].eval(env.clone(), individuals) as u8 } else { 255 };
@@ -368,12 +596,362 @@ impl Exp {
                 Value::Int(0)
             }
             
-            //for unknown functions, just return 0
-            _ => Value::Int(self.eval(env, individuals)),
+            //random(min, max) - floats if either bound is a float
+            "random" => {
+                if args.len() == 2 {
+                    let min = args[0].eval_to_val(env.clone(), individuals);
+                    let max = args[1].eval_to_val(env, individuals);
+                    return random_between(&min, &max);
+                }
+                Value::Int(0)
+            }
+
+            //user FUNCTION, or 0 for unknown functions
+            _ => call_function(name, args, line, env, individuals),
+        }
+    }
+}
+
+//shared by the tree-walker and the bytecode vm (vm.rs) so both give the same results
+
+//variable lookup: local/creature store, then the environment keyword, then self
+#[allow(clippy::collapsible_if)]
+pub fn lookup_var(env: &Arc<RwLock<Environment>>, name: &str) -> Value {
+    let env_ref = env.read().unwrap();
+    //check local/creature store
+    if let Some(v) = env_ref.store.get(name) {
+        return v.clone();
+    }
+    
+    if name == "environment" {
+        return Value::Environment;
+    }
+
+    //then check if we have a 'self' and look there
+    if let Some(Value::Object(self_env)) = env_ref.store.get("self") {
+        if let Some(v) = self_env.read().unwrap().store.get(name) {
+            return v.clone();
+        }
+    }
+    
+    Value::Int(0)
+}
+
+//obj.field
+pub fn read_field(obj_val: Value, field: &str, line: usize) -> Value {
+    if let Value::Object(obj_env) = obj_val {
+        obj_env.read().unwrap().store.get(field).cloned().unwrap_or(Value::Int(0))
+    } else {
+        raise(line, format!("cannot read field '{}' of {}", field, obj_val.type_name()));
+        Value::Int(0)
+    }
+}
+
+//list[i], environment[x] and environment[x][y]
+pub fn index_value(list_val: Value, idx: i32, line: usize, individuals: &[Individual]) -> Value {
+    match list_val {
+        //normal list access: my_list[i]
+        Value::List(list) => {
+            let borrowed = list.read().unwrap();
+            if idx >= 0 && (idx as usize) < borrowed.len() {
+                borrowed[idx as usize].clone()
+            } else {
+                raise(line, format!("index {} out of range for list of length {}", idx, borrowed.len()));
+                Value::Int(0)
+            }
+        }
+        //grid access: environment[x]
+        Value::Environment => Value::GridRow(idx),
+        //grid cell access: environment[x][y]
+        Value::GridRow(x) => {
+            let y = idx;
+            
+            //get world size for wrapping
+            let (width, height) = WORLD_DIMENSIONS.with(|d| *d.borrow());
+            let wrapped_x = ((x % width) + width) % width;
+            let wrapped_y = ((y % height) + height) % height;
+
+            //try cache first (faster)
+            let cached = GRID_CACHE.with(|cache| {
+                cache.borrow().as_ref().and_then(|index| index.first_alive_in_cell(wrapped_x, wrapped_y))
+            });
+            
+            if let Some(found) = cached {
+                return Value::Object(found);
+            }
+
+            //search through individuals
+            for ind in individuals {
+                let env_b = ind.env.read().unwrap();
+                if env_b.dead {
+                    continue;
+                }
+                let store = &env_b.store;
+                let ind_x = store.get("x").map_or(0, |v| v.to_int());
+                let ind_y = store.get("y").map_or(0, |v| v.to_int());
+                if (ind_x % width + width) % width == wrapped_x && 
+                   (ind_y % height + height) % height == wrapped_y {
+                    return Value::Object(ind.env.clone());
+                }
+            }
+            Value::Int(0)
+        }
+        other => {
+            raise(line, format!("cannot index into {}", other.type_name()));
+            Value::Int(0)
+        }
+    }
+}
+
+//first living individual on a cell
+pub fn get_at(x: i32, y: i32, individuals: &[Individual]) -> Value {
+    //Try cache first
+    let cached = GRID_CACHE.with(|cache| {
+        cache.borrow().as_ref().and_then(|index| index.first_alive_at(x, y))
+    });
+    
+    if let Some(found) = cached {
+        return Value::Object(found);
+    }
+
+    for ind in individuals {
+        let env_b = ind.env.read().unwrap();
+        if env_b.dead {
+            continue;
+        }
+        let store = &env_b.store;
+        let ind_x = store.get("x").map_or(0, |v| v.to_int());
+        let ind_y = store.get("y").map_or(0, |v| v.to_int());
+        if ind_x == x && ind_y == y {
+            return Value::Object(ind.env.clone());
+        }
+    }
+    Value::Int(0)
+}
+
+//every living individual on a cell
+pub fn all_at(x: i32, y: i32, individuals: &[Individual]) -> Value {
+    let mut found = Vec::new();
+    let cached = GRID_CACHE.with(|cache| {
+        cache.borrow().as_ref().map(|index| index.alive_at(x, y))
+    });
+
+    if let Some(occupants) = cached {
+        found = occupants;
+    } else {
+        for ind in individuals {
+            let env_b = ind.env.read().unwrap();
+            if !env_b.dead && position(&env_b) == Some((x, y)) {
+                found.push(ind.env.clone());
+            }
+        }
+    }
+    let list = found.into_iter().map(Value::Object).collect();
+    Value::List(Arc::new(RwLock::new(list)))
+}
+
+pub fn nearest(env: &Arc<RwLock<Environment>>, species: &str, radius: i32, individuals: &[Individual]) -> Value {
+    for (_, other) in neighborhood(env, radius, Metric::Manhattan, individuals) {
+        if is_species(&other, species) {
+            return Value::Object(other);
+        }
+    }
+    Value::Int(0)
+}
+
+pub fn count_in_radius(env: &Arc<RwLock<Environment>>, species: &str, radius: i32, individuals: &[Individual]) -> Value {
+    let mut count = 0;
+    for (_, other) in neighborhood(env, radius, Metric::Manhattan, individuals) {
+        if is_species(&other, species) {
+            count += 1;
+        }
+    }
+    Value::Int(count)
+}
+
+pub fn neighbors(env: &Arc<RwLock<Environment>>, radius: i32, metric: Metric, individuals: &[Individual]) -> Value {
+    let mut found = Vec::new();
+    for (_, other) in neighborhood(env, radius, metric, individuals) {
+        found.push(Value::Object(other));
+    }
+    Value::List(Arc::new(RwLock::new(found)))
+}
+
+pub fn kill(target: Value) {
+    if let Value::Object(obj) = target {
+        write_target(obj).write().unwrap().dead = true;
+    }
+}
+
+//straight line distance between two objects
+pub fn dist(obj1: &Value, obj2: &Value) -> Value {
+    if let (Value::Object(o1), Value::Object(o2)) = (obj1, obj2) {
+        let x1 = o1.read().unwrap().store.get("x").map_or(0.0, |v| v.to_float());
+        let y1 = o1.read().unwrap().store.get("y").map_or(0.0, |v| v.to_float());
+        let x2 = o2.read().unwrap().store.get("x").map_or(0.0, |v| v.to_float());
+        let y2 = o2.read().unwrap().store.get("y").map_or(0.0, |v| v.to_float());
+        
+        let dx = x1 - x2;
+        let dy = y1 - y2;
+        let distance = (dx * dx + dy * dy).sqrt();
+        
+        return Value::Float(distance);
+    }
+    Value::Int(0)
+}
+
+//die; - mark self for removal at the end of the step
+pub fn die(env: &Arc<RwLock<Environment>>) {
+    let self_env = env.read().unwrap().store.get("self").cloned();
+    if let Some(Value::Object(self_env)) = self_env {
+        self_env.write().unwrap().dead = true;
+    }
+}
+
+//arithmetic with numeric promotion: int op int stays int,
+//anything involving a float is done in floats
+pub fn binary_op(left: &Value, op: &str, right: &Value, line: usize) -> Value {
+    if (op == "/" || op == "%") && right.to_float() == 0.0 {
+        raise(line, "division by zero".to_string());
+    }
+
+    if matches!(left, Value::Float(_)) || matches!(right, Value::Float(_)) {
+        let l = left.to_float();
+        let r = right.to_float();
+        return Value::Float(match op {
+            "+" => l + r,
+            "-" => l - r,
+            "*" => l * r,
+            "/" if r != 0.0 => l / r,
+            "%" if r != 0.0 => l % r,
+            _ => 0.0, //unknown operator or division by zero
+        });
+    }
+
+    let l = left.to_int();
+    let r = right.to_int();
+    Value::Int(match op {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        "/" if r != 0 => l / r,
+        "%" if r != 0 => l % r,
+        _ => 0, //unknown operator or division by zero
+    })
+}
+
+//uniform random number in [min, max) using the world's rng
+pub fn random_between(min: &Value, max: &Value) -> Value {
+    if matches!(min, Value::Float(_)) || matches!(max, Value::Float(_)) {
+        let (lo, hi) = (min.to_float(), max.to_float());
+        if hi > lo {
+            return Value::Float(RNG.with(|rng| rng.borrow_mut().gen_range(lo..hi)));
         }
+        return Value::Float(lo);
+    }
+
+    let (lo, hi) = (min.to_int(), max.to_int());
+    if hi > lo {
+        Value::Int(RNG.with(|rng| rng.borrow_mut().gen_range(lo..hi)))
+    } else {
+        Value::Int(lo)
     }
 }
 
+//current FUNCTION nesting depth, or None (after reporting it) when
+//another call would go over the recursion limit
+pub fn check_call_depth(name: &str, line: usize, strict: bool) -> Option<usize> {
+    let depth = call_depth();
+    if depth >= MAX_CALL_DEPTH {
+        let message = format!("FUNCTION {} exceeded the recursion limit of {}", name, MAX_CALL_DEPTH);
+        if strict {
+            raise(line, message);
+        } else {
+            warn(line, message);
+        }
+        return None;
+    }
+    Some(depth)
+}
+
+pub fn call_depth() -> usize {
+    CALL_DEPTH.with(|d| d.get())
+}
+
+pub fn set_call_depth(depth: usize) {
+    CALL_DEPTH.with(|d| d.set(depth));
+}
+
+//hand an error from a FUNCTION body back to the command that made the call
+pub fn pass_error(err: RuntimeError) {
+    PENDING_ERROR.with(|e| *e.borrow_mut() = Some(err));
+}
+
+//call a user FUNCTION: arguments are bound in a fresh local scope
+//(plus the caller's self) and the body runs until it returns
+fn call_function(
+    name: &str,
+    args: &[Exp],
+    line: usize,
+    env: Arc<RwLock<Environment>>,
+    individuals: &[Individual],
+) -> Value {
+    let program = match PROGRAM.with(|p| p.borrow().clone()) {
+        Some(program) => program,
+        None => return Value::Int(0),
+    };
+    let func = match program.functions_block.get(name) {
+        Some(func) => func,
+        None => {
+            raise(line, format!("unknown function '{}'", name));
+            return Value::Int(0);
+        }
+    };
+
+    let depth = match check_call_depth(&func.name, line, program.strict) {
+        Some(depth) => depth,
+        None => return Value::Int(0),
+    };
+
+    //evaluate arguments in the caller's scope
+    let local_env = Environment::new();
+    {
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(arg.eval_to_val(env.clone(), individuals));
+        }
+        let mut local = local_env.write().unwrap();
+        for (param, value) in func.params.iter().zip(values) {
+            local.store.insert(param.clone(), value);
+        }
+        if let Some(self_val) = env.read().unwrap().store.get("self") {
+            local.store.insert("self".to_string(), self_val.clone());
+        }
+    }
+
+    set_call_depth(depth + 1);
+    let mut spawner = Vec::new();
+    let mut result = Value::Int(0);
+    for cmd in &func.body {
+        match cmd.execute(local_env.clone(), individuals, &mut spawner, &program) {
+            Ok(Some(value)) => {
+                result = value;
+                break;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                pass_error(err);
+                break;
+            }
+        }
+    }
+    set_call_depth(depth);
+
+    //memory fix: clear the local scope to break reference cycles
+    local_env.write().unwrap().store.clear();
+    result
+}
+
 //boolean expression evaluation
 
 impl BExp {
@@ -406,34 +984,45 @@ impl BExp {
             
             //a > b
             BExp::Greater(left, right) => {
-                left.eval(env.clone(), individuals) > right.eval(env, individuals)
+                let left_val = left.eval_to_val(env.clone(), individuals);
+                let right_val = right.eval_to_val(env, individuals);
+                matches!(compare_numbers(&left_val, &right_val), Some(Ordering::Greater))
             }
             
             //a < b
             BExp::Less(left, right) => {
-                left.eval(env.clone(), individuals) < right.eval(env, individuals)
+                let left_val = left.eval_to_val(env.clone(), individuals);
+                let right_val = right.eval_to_val(env, individuals);
+                matches!(compare_numbers(&left_val, &right_val), Some(Ordering::Less))
             }
             
             //a >= b
             BExp::GreaterEqual(left, right) => {
-                left.eval(env.clone(), individuals) >= right.eval(env, individuals)
+                let left_val = left.eval_to_val(env.clone(), individuals);
+                let right_val = right.eval_to_val(env, individuals);
+                matches!(compare_numbers(&left_val, &right_val), Some(Ordering::Greater | Ordering::Equal))
             }
             
             //a <= b
             BExp::LessEqual(left, right) => {
-                left.eval(env.clone(), individuals) <= right.eval(env, individuals)
+                let left_val = left.eval_to_val(env.clone(), individuals);
+                let right_val = right.eval_to_val(env, individuals);
+                matches!(compare_numbers(&left_val, &right_val), Some(Ordering::Less | Ordering::Equal))
             }
         }
     }
 }
 
 //helper function to compare two values
-fn values_are_equal(a: &Value, b: &Value) -> bool {
+pub fn values_are_equal(a: &Value, b: &Value) -> bool {
     match (a, b) {
         (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Float(_), Value::Int(_) | Value::Float(_)) |
+        (Value::Int(_), Value::Float(_)) => a.to_float() == b.to_float(),
         (Value::String(x), Value::String(y)) => x == y,
         (Value::Bool(x), Value::Bool(y)) => x == y,
-        (Value::Object(x), Value::Object(y)) => Arc::ptr_eq(x, y),
+        //a previous-state copy is the same individual as its live one
+        (Value::Object(x), Value::Object(y)) => Arc::ptr_eq(&write_target(x.clone()), &write_target(y.clone())),
         //null checks (0 means "nothing")
         (Value::Int(0), Value::Object(_)) => false,
         (Value::Object(_), Value::Int(0)) => false,
@@ -441,29 +1030,61 @@ fn values_are_equal(a: &Value, b: &Value) -> bool {
     }
 }
 
+//helper function to order two values as numbers (floats if either is a float)
+pub fn compare_numbers(a: &Value, b: &Value) -> Option<Ordering> {
+    if matches!(a, Value::Float(_)) || matches!(b, Value::Float(_)) {
+        a.to_float().partial_cmp(&b.to_float())
+    } else {
+        Some(a.to_int().cmp(&b.to_int()))
+    }
+}
+
 //command execution
 
 impl Command {
-    //run a command and maybe return a value (for return statements)
+    //run a command and maybe return a value (for return statements).
+    //in strict mode a failed expression stops the command with a RuntimeError
     pub fn execute(
         &self,
         env: Arc<RwLock<Environment>>,
         individuals: &[Individual],
         spawner: &mut Vec<Individual>,
         program: &Program,
-    ) -> Option<Value> {
+    ) -> Result<Option<Value>, RuntimeError> {
+        //--debug: breakpoints and stepping
+        if !program.debug {
+            return self.run(env, individuals, spawner, program);
+        }
+        debugger::before(self, &env);
+        let result = self.run(env, individuals, spawner, program);
+        debugger::after(self);
+        result
+    }
+
+    fn run(
+        &self,
+        env: Arc<RwLock<Environment>>,
+        individuals: &[Individual],
+        spawner: &mut Vec<Individual>,
+        program: &Program,
+    ) -> Result<Option<Value>, RuntimeError> {
         match self {
             //just evaluate an expression (for function calls like push())
             Command::Exp(exp, _line) => {
                 exp.eval_to_val(env, individuals);
-                None
+                check_error()?;
+                Ok(None)
             }
             
             //spawn species @ (x, y)
             Command::Spawn { species, x, y, line: _line } => {
                 if let Some(species_def) = program.species_block.get(species) {
-                    let x_pos = x.eval(env.clone(), individuals);
-                    let y_pos = y.eval(env, individuals);
+                    let x_pos = x.eval_to_val(env.clone(), individuals).to_number();
+                    let y_pos = y.eval_to_val(env, individuals).to_number();
+                    check_error()?;
+                    if !spawn_allowed(&x_pos, &y_pos, individuals, spawner, program) {
+                        return Ok(None);
+                    }
                     
                     //create new individual
                     let new_env = Environment::new();
@@ -478,18 +1099,22 @@ impl Command {
                         }
                         
                         //set position
-                        env_mut.store.insert("x".to_string(), Value::Int(x_pos));
-                        env_mut.store.insert("y".to_string(), Value::Int(y_pos));
+                        env_mut.store.insert("x".to_string(), x_pos);
+                        env_mut.store.insert("y".to_string(), y_pos);
                     }
+                    check_error()?;
                     
-                    spawner.push(Individual {
-                        species: species.clone(),
-                        env: new_env,
-                    });
+                    spawner.push(Individual::new(species.clone(), new_env));
                 }
-                None
+                Ok(None)
             }
             
+            //die; - mark self for removal at the end of the step
+            Command::Die(_line) => {
+                die(&env);
+                Ok(None)
+            }
+
             //print(a, b, c)
             Command::Print(expressions, _line) => {
                 let mut parts = Vec::new();
@@ -497,74 +1122,92 @@ impl Command {
                     let value = exp.eval_to_val(env.clone(), individuals);
                     parts.push(value.to_string());
                 }
+                check_error()?;
                 println!("{}", parts.join(" "));
-                None
+                Ok(None)
             }
             
             //x = value
-            Command::Assign { target, value, line: _line } => {
+            Command::Assign { target, value, line } => {
                 let new_value = value.eval_to_val(env.clone(), individuals);
+                check_error()?;
                 
                 match target {
                     //simple variable: x = 5
-                    Exp::Var(name, _l) => {
-                        let mut env_ref = env.write().unwrap();
-                        env_ref.store.insert(name.clone(), new_value);
-                    }
+                    Exp::Var(name, _l) => set_var(&env, name, new_value),
                     //object field: self.x = 5
                     Exp::Dot(obj_exp, field, _l) => {
-                        if let Value::Object(obj_env) = obj_exp.eval_to_val(env, individuals) {
-                            obj_env.write().unwrap().store.insert(field.clone(), new_value);
+                        match obj_exp.eval_to_val(env, individuals) {
+                            Value::Object(obj_env) => {
+                                let obj_env = write_target(obj_env);
+                                track_move(&obj_env, field, &new_value);
+                                obj_env.write().unwrap().store.insert(field.clone(), new_value);
+                            }
+                            other => raise(*line, format!("cannot set field '{}' on {}", field, other.type_name())),
                         }
                     }
                     //list index: genes[i] = 5
                     Exp::Index(list_exp, idx_exp, _l) => {
-                        if let Value::List(list) = list_exp.eval_to_val(env.clone(), individuals) {
-                            let idx = idx_exp.eval(env, individuals) as usize;
-                            let mut borrowed = list.write().unwrap();
-                            if idx < borrowed.len() {
-                                borrowed[idx] = new_value;
+                        match list_exp.eval_to_val(env.clone(), individuals) {
+                            Value::List(list) => {
+                                let idx = idx_exp.eval(env, individuals);
+                                let list = write_list(list);
+                                let mut borrowed = list.write().unwrap();
+                                if idx >= 0 && (idx as usize) < borrowed.len() {
+                                    borrowed[idx as usize] = new_value;
+                                } else {
+                                    raise(*line, format!("index {} out of range for list of length {}", idx, borrowed.len()));
+                                }
                             }
+                            other => raise(*line, format!("cannot index into {}", other.type_name())),
                         }
                     }
                     _ => {}
                 }
-                None
+                check_error()?;
+                Ok(None)
             }
             
             //if (condition) { ... } else { .... }
             Command::If { condition, then_block, else_block, line: _line } => {
-                if condition.eval(env.clone(), individuals) {
+                let taken = condition.eval(env.clone(), individuals);
+                check_error()?;
+                if taken {
                     //run then block
                     for cmd in then_block {
-                        let result = cmd.execute(env.clone(), individuals, spawner, program);
+                        let result = cmd.execute(env.clone(), individuals, spawner, program)?;
                         if result.is_some() {
-                            return result; 
+                            return Ok(result);
                         }
                     }
                 } else if let Some(else_cmds) = else_block {
                     //run else block
                     for cmd in else_cmds {
-                        let result = cmd.execute(env.clone(), individuals, spawner, program);
+                        let result = cmd.execute(env.clone(), individuals, spawner, program)?;
                         if result.is_some() {
-                            return result;
+                            return Ok(result);
                         }
                     }
                 }
-                None
+                Ok(None)
             }
             
             //while (cond) { ... }
             Command::While { condition, body, line: _line } => {
-                while condition.eval(env.clone(), individuals) {
+                loop {
+                    let running = condition.eval(env.clone(), individuals);
+                    check_error()?;
+                    if !running {
+                        break;
+                    }
                     for cmd in body {
-                        let result = cmd.execute(env.clone(), individuals, spawner, program);
+                        let result = cmd.execute(env.clone(), individuals, spawner, program)?;
                         if result.is_some() {
-                            return result;
+                            return Ok(result);
                         }
                     }
                 }
-                None
+                Ok(None)
             }
             
             //for item in environment { ... }
@@ -572,22 +1215,27 @@ impl Command {
             Command::For { var, collection, body, line: _line } => {
                 if collection == "environment" {
                     for ind in individuals {
-                        env.write().unwrap().store.insert(var.clone(), Value::Object(ind.env.clone()));
+                        //skip anyone who already died this step
+                        if ind.env.read().unwrap().dead {
+                            continue;
+                        }
+                        set_var(&env, var, Value::Object(ind.env.clone()));
                         for cmd in body {
-                            let result = cmd.execute(env.clone(), individuals, spawner, program);
+                            let result = cmd.execute(env.clone(), individuals, spawner, program)?;
                             if result.is_some() {
-                                return result;
+                                return Ok(result);
                             }
                         }
                     }
                 }
-                None
+                Ok(None)
             }
             
             //return value
             Command::Return(exp, _line) => {
                 let value = exp.eval_to_val(env, individuals);
-                Some(value)
+                check_error()?;
+                Ok(Some(value))
             }
         }
     }
@@ -1,14 +1,18 @@
 //evolution.rs - evolutionary algorithm logic
 //handles the core evolutionary operations:
-//- generation creation and selection
+//- generation creation and selection (truncation, tournament, roulette, rank)
 //- crossover between parents
 //- memory management for generations
 //- snapshot creation for history
 
-use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 use crate::types::*;
+use crate::bytecode::Block;
+use crate::vm::run_command;
 
 //create a snapshot of individuals for history
 pub fn snapshot_individuals(individuals: &[Individual], program: &Program) -> Vec<Individual> {
@@ -18,8 +22,8 @@ pub fn snapshot_individuals(individuals: &[Individual], program: &Program) -> Ve
         
         //optimization- only copy persistent state defined in species scheme.
         //this acts as a garbage collector temporary variables created are not stored in the history, preventing memory bloat.
-        let mut store = HashMap::new();
         let env_read = ind.env.read().unwrap();
+        let mut store = env_read.store.empty_like();
 
         //1. copy 'x', 'y' (system variables)
         if let Some(val) = env_read.store.get("x") { store.insert("x".to_string(), val.clone()); }
@@ -30,7 +34,7 @@ pub fn snapshot_individuals(individuals: &[Individual], program: &Program) -> Ve
 
         //3. copy variables defined in the species block (genetic/state memory)
         if let Some(species_def) = program.species_block.get(&ind.species) {
-            for key in species_def.properties.keys() {
+            for (key, _) in &species_def.properties {
                 if let Some(val) = env_read.store.get(key) {
                     store.insert(key.clone(), val.deep_copy());
                 }
@@ -45,6 +49,7 @@ pub fn snapshot_individuals(individuals: &[Individual], program: &Program) -> Ve
         
         new_env.write().unwrap().store = store;
         snapshot.push(Individual {
+            id: ind.id,
             species: ind.species.clone(),
             env: new_env,
         });
@@ -52,61 +57,48 @@ pub fn snapshot_individuals(individuals: &[Individual], program: &Program) -> Ve
     snapshot
 }
 
-//create next generation from current best instances
+//create next generation from current best instances.
+//instances must already be sorted best first
+#[allow(clippy::ptr_arg)]
 pub fn create_next_generation(
     instances: &mut Vec<World>,
     program: &Arc<Program>,
     num_instances: i32,
     current_gen: i32,
 ) -> Vec<World> {
-    let keep_count = (num_instances / 2).max(1) as usize;
+    let settings = &program.evolve_block;
+    let survivors = (settings.survivor_count().max(1) as usize).min(instances.len());
+    let elitism = (settings.elitism.max(0) as usize).min(instances.len());
+
+    //parent picks get their own stream so they don't shift the worlds' rngs
+    let mut rng = StdRng::seed_from_u64(derive_seed(settings.seed.unwrap_or(0), -1, current_gen));
     let mut next_gen = Vec::new();
     
     for i in 0..num_instances as usize {
-        let parent_idx = i % keep_count;
         let mut child = World::new(program.clone(), i as i32);
         child.generation = current_gen;
-        
-        //copy individuals from parent
-        for ind in &instances[parent_idx].individuals {
-            let child_env = Environment::new();
-            
-            //optimization: garbage collect transient variables.
-            // recreate the child based only on the species schema (dna) plus its position. any temporary variables are dropped.
-            let mut store = HashMap::new();
-            let parent_env_read = ind.env.read().unwrap();
-            
-            //1. copy position
-            if let Some(val) = parent_env_read.store.get("x") { store.insert("x".to_string(), val.clone()); }
-            if let Some(val) = parent_env_read.store.get("y") { store.insert("y".to_string(), val.clone()); }
-            
-            //2. copy species string (needed for species checking in fitness/routines)
-            store.insert("species".to_string(), Value::String(ind.species.clone()));
-
-            //3. copy schema properties (deep copy)
-            if let Some(species_def) = program.species_block.get(&ind.species) {
-                for key in species_def.properties.keys() {
-                    if let Some(val) = parent_env_read.store.get(key) {
-                        store.insert(key.clone(), val.deep_copy());
-                    }
-                }
-            } else {
-                store = parent_env_read.deep_copy_store();
-            }
-            
-            //fix self to point to new environment
-            store.insert("self".to_string(), Value::Object(child_env.clone()));
-            
-            child_env.write().unwrap().store = store;
-            child.individuals.push(Individual {
-                species: ind.species.clone(),
-                env: child_env,
-            });
+        child.reseed();
+
+        //elites: the best instances go through untouched
+        if i < elitism {
+            copy_individuals(&mut child, &instances[i], program);
+            next_gen.push(child);
+            continue;
         }
+
+        let (parent1, parent2) = match settings.selection {
+            Selection::Truncation => (i % survivors, (i + 1) % survivors),
+            _ => (
+                select_parent(instances, survivors, settings.selection, &mut rng),
+                select_parent(instances, survivors, settings.selection, &mut rng),
+            ),
+        };
+        copy_individuals(&mut child, &instances[parent1], program);
         
-        //apply crossover for non-elite children
-        if i >= keep_count {
-            apply_crossover(&mut child, instances, i, keep_count, program);
+        //truncation keeps the first copy of each survivor as is,
+        //every other strategy always breeds two parents
+        if settings.selection != Selection::Truncation || i >= survivors {
+            apply_crossover(&mut child, &instances[parent2], program);
         }
 
         child.mutate();
@@ -122,22 +114,121 @@ pub fn create_next_generation(
     next_gen
 }
 
-//apply crossover between parents
-fn apply_crossover(
-    child: &mut World,
-    instances: &[World],
-    i: usize,
-    keep_count: usize,
-    program: &Program,
-) {
-    let p2_idx = (i + 1) % keep_count;
-    let p2 = &instances[p2_idx];
-    
+//pick a parent index among the best `survivors` instances
+fn select_parent(instances: &[World], survivors: usize, selection: Selection, rng: &mut StdRng) -> usize {
+    match selection {
+        Selection::Truncation => rng.gen_range(0..survivors),
+
+        //the fittest of `size` random contenders
+        Selection::Tournament(size) => {
+            let mut best = rng.gen_range(0..survivors);
+            for _ in 1..size {
+                let contender = rng.gen_range(0..survivors);
+                if instances[contender].fitness > instances[best].fitness {
+                    best = contender;
+                }
+            }
+            best
+        }
+
+        //chance proportional to fitness, see roulette_weights
+        Selection::Roulette => {
+            let mut fitness = Vec::new();
+            for w in &instances[..survivors] {
+                fitness.push(w.fitness);
+            }
+            pick_weighted(&roulette_weights(&fitness), rng)
+        }
+
+        //best gets weight n, the next n - 1, ... the last 1
+        Selection::Rank => {
+            let mut weights = Vec::new();
+            for rank in 0..survivors {
+                weights.push((survivors - rank) as u64);
+            }
+            pick_weighted(&weights, rng)
+        }
+    }
+}
+
+//the fitness itself is the weight. a fitness of 0 or less can't be, so then
+//everyone is shifted up until the lowest is 0. all 0: everyone equally likely
+pub fn roulette_weights(fitness: &[i32]) -> Vec<u64> {
+    let lowest = fitness.iter().copied().min().unwrap_or(0) as i64;
+    let shift = if lowest <= 0 { -lowest } else { 0 };
+    let mut weights = Vec::new();
+    for &f in fitness {
+        weights.push((f as i64 + shift) as u64);
+    }
+    if weights.iter().all(|&w| w == 0) {
+        return vec![1; weights.len()];
+    }
+    weights
+}
+
+//index picked with probability proportional to its weight
+fn pick_weighted(weights: &[u64], rng: &mut StdRng) -> usize {
+    let total: u64 = weights.iter().sum();
+    let mut ticket = rng.gen_range(0..total.max(1));
+    for (i, &w) in weights.iter().enumerate() {
+        if ticket < w {
+            return i;
+        }
+        ticket -= w;
+    }
+    weights.len() - 1
+}
+
+//copy a parent's individuals into a child world
+fn copy_individuals(child: &mut World, parent: &World, program: &Program) {
+    for ind in &parent.individuals {
+        let child_env = Environment::new();
+        
+        //optimization: garbage collect transient variables.
+        // recreate the child based only on the species schema (dna) plus its position. any temporary variables are dropped.
+        let parent_env_read = ind.env.read().unwrap();
+        let mut store = parent_env_read.store.empty_like();
+        
+        //1. copy position
+        if let Some(val) = parent_env_read.store.get("x") { store.insert("x".to_string(), val.clone()); }
+        if let Some(val) = parent_env_read.store.get("y") { store.insert("y".to_string(), val.clone()); }
+        
+        //2. copy species string (needed for species checking in fitness/routines)
+        store.insert("species".to_string(), Value::String(ind.species.clone()));
+
+        //3. copy schema properties (deep copy)
+        if let Some(species_def) = program.species_block.get(&ind.species) {
+            for (key, _) in &species_def.properties {
+                if let Some(val) = parent_env_read.store.get(key) {
+                    store.insert(key.clone(), val.deep_copy());
+                }
+            }
+        } else {
+            store = parent_env_read.deep_copy_store();
+        }
+        
+        //fix self to point to new environment
+        store.insert("self".to_string(), Value::Object(child_env.clone()));
+        
+        child_env.write().unwrap().store = store;
+        child.individuals.push(Individual {
+            id: ind.id,
+            species: ind.species.clone(),
+            env: child_env,
+        });
+    }
+}
+
+//apply crossover between the child (a copy of parent 1) and parent 2
+#[allow(clippy::collapsible_if)]
+fn apply_crossover(child: &mut World, p2: &World, program: &Program) {
     if let Some(rule) = program.mutations_block.iter()
         .find(|r| r.action == "crossover") 
     {
         if let Some(body) = &rule.body {
-            for j in 0..child.individuals.len() {
+            child.swap_thread_state();
+            //parents can differ in size once individuals have died
+            for j in 0..child.individuals.len().min(p2.individuals.len()) {
                 let crossover_env = Environment::new();
                 {
                     let mut env_mut = crossover_env.write().unwrap();
@@ -146,14 +237,25 @@ fn apply_crossover(
                     env_mut.store.insert("child".to_string(), Value::Object(child.individuals[j].env.clone()));
                 }
                 
+                let start = program.profile.then(Instant::now);
                 let mut spawner = Vec::new();
-                for cmd in body {
-                    cmd.execute(crossover_env.clone(), &[], &mut spawner, program);
+                let mut error = None;
+                for (c, cmd) in body.iter().enumerate() {
+                    if let Err(e) = run_command(Block::Mutation("crossover"), c, cmd, crossover_env.clone(), &[], &mut spawner, program) {
+                        error = Some(e.within("MUTATE crossover", Some(j)));
+                        break;
+                    }
                 }
+                child.profile.record("MUTATE crossover", start);
                 
                 //memory fix: clear crossover_env to break reference cycles
                 crossover_env.write().unwrap().store.clear();
+                if let Some(e) = error {
+                    child.record_error(e);
+                    break;
+                }
             }
+            child.swap_thread_state();
         }
     }
 }
@@ -191,3 +293,34 @@ pub fn clear_world_history(instances: &mut [World]) {
         w.record_history = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roulette_weights_are_the_fitness() {
+        assert_eq!(roulette_weights(&[1001, 1000]), vec![1001, 1000]);
+        assert_eq!(roulette_weights(&[9, 3, 1]), vec![9, 3, 1]);
+    }
+
+    #[test]
+    fn roulette_weights_shift_up_when_fitness_is_not_positive() {
+        assert_eq!(roulette_weights(&[5, 0, -3]), vec![8, 3, 0]);
+        assert_eq!(roulette_weights(&[4, 0]), vec![4, 0]);
+    }
+
+    #[test]
+    fn roulette_weights_fall_back_to_uniform() {
+        assert_eq!(roulette_weights(&[0, 0, 0]), vec![1, 1, 1]);
+        assert_eq!(roulette_weights(&[-2, -2]), vec![1, 1]);
+    }
+
+    #[test]
+    fn pick_weighted_never_picks_weight_zero() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            assert_ne!(pick_weighted(&[3, 0, 1], &mut rng), 1);
+        }
+    }
+}
@@ -1,204 +1,275 @@
 //gui.rs - graphical user interface using egui
 
-use std::collections::HashMap;
 use std::sync::Arc;
-use rayon::prelude::*;
-
+use std::time::{Duration, Instant};
 use eframe::egui;
+use egui_plot::{Legend, Line, Plot, PlotPoints, VLine};
 
 use crate::types::*;
-use crate::eval::{DRAW_COMMANDS, GRID_CACHE, WORLD_DIMENSIONS};
-use crate::evolution::{
-    snapshot_individuals, create_next_generation, 
-    clear_snapshot_memory, clear_world_history
-};
+use crate::spatial::position;
+use crate::eval::DRAW_COMMANDS;
+use crate::render::{export, run_visualize};
+use crate::reload::{SourceWatcher, POLL_INTERVAL, load_program, only_visualize_changed};
+use crate::editor::Editor;
+use crate::diagnostic::{has_errors, Diagnostic};
+use crate::simulation::Simulation;
 
 //application state
 
 pub struct SimApp {
-    pub instances: Vec<World>,
-    pub history: Vec<GenerationSnapshot>,
+    pub sim: Simulation,
     pub current_gen_idx: usize,
     pub current_step_idx: i32,
     pub world_width: i32,
     pub world_height: i32,
-    pub program: Arc<Program>,
-    pub num_generations: i32,
-    pub num_instances: i32,
-    pub current_gen: i32,
     pub running: bool,
-    pub global_best_fitness: i32,
+    pub plot_series: Vec<String>, //extra species properties charted next to fitness
+    pub new_series: String,       //text box for adding a plot series
+    pub inspected: Option<Inspection>, //cell clicked on the canvas
+    pub zoom: f32,                     //canvas scale, 1.0 shows the whole world
+    pub pan: egui::Vec2,               //canvas offset in screen pixels
+    pub playing: bool,                 //step replay advancing on its own
+    pub loop_playback: bool,           //start over after the last step
+    pub fps: f32,                      //replay steps per second
+    pub auto_play: bool,               //replay every new generation while evolution runs
+    pub last_frame: Instant,           //when the replay last moved a step
+    pub export_path: String,           //.gif file or directory for PNG frames
+    pub export_status: Option<String>, //result of the last export
+    pub watcher: Option<SourceWatcher>, //source file checked for edits (hot reload)
+    pub source: String,                //text of the running program
+    pub reload_errors: Vec<Diagnostic>,   //parse/semantic errors of the last reload
+    pub pending_reload: Option<(Program, String)>, //changed program and its text, waiting for a restart
+    pub editor: Editor,                //in-app source editor
+}
+
+//who was standing on the clicked cell. they are followed by id while
+//scrubbing through steps, even after they walk off the cell
+pub struct Inspection {
+    pub cell: (i32, i32),
+    pub ids: Vec<u64>,
 }
 
 impl SimApp {
-    pub fn new(
-        instances: Vec<World>,
-        program: Arc<Program>,
-        num_generations: i32,
-        num_instances: i32,
-    ) -> Self {
+    pub fn new(sim: Simulation) -> Self {
         Self {
-            instances,
-            history: Vec::new(),
             current_gen_idx: 0,
             current_step_idx: 1,
-            world_width: program.env_width,
-            world_height: program.env_height,
-            program,
-            num_generations,
-            num_instances,
-            current_gen: 0,
+            world_width: sim.program.env_width,
+            world_height: sim.program.env_height,
+            sim,
             running: false,
-            global_best_fitness: 0,
+            plot_series: Vec::new(),
+            new_series: String::new(),
+            inspected: None,
+            zoom: 1.0,
+            pan: egui::Vec2::ZERO,
+            playing: false,
+            loop_playback: false,
+            fps: 10.0,
+            auto_play: false,
+            last_frame: Instant::now(),
+            export_path: "replay.gif".to_string(),
+            export_status: None,
+            watcher: None,
+            source: String::new(),
+            reload_errors: Vec::new(),
+            pending_reload: None,
+            editor: Editor::new(),
         }
     }
 
-    //run one generation of evolution
-    pub fn run_generation(&mut self) {
-        let start = std::time::Instant::now();
-        
-        if self.current_gen >= self.num_generations {
-            self.running = false;
-            return;
-        }
-        
-        self.current_gen += 1;
-        let g = self.current_gen;
+    //reload the program whenever the source file is saved
+    pub fn watch(&mut self, path: &str, source: &str) {
+        self.watcher = Some(SourceWatcher::new(path));
+        self.source = source.to_string();
+    }
 
-        //run simulation steps in parallel using Rayon
-        let env_steps = self.program.env_steps;
-        
-        //enable history recording
-        for w in &mut self.instances {
-            w.record_history = true;
-            w.history.clear();
+    //a VISUALIZE-only edit is swapped in right away and redraws the existing
+    //history. anything else waits for the user to restart evolution
+    fn check_source(&mut self) {
+        let text = match self.watcher.as_mut().and_then(|w| w.poll()) {
+            Some(text) => text,
+            None => return,
+        };
+        //back to what is running: nothing to apply anymore
+        if text == self.source {
+            self.reload_errors.clear();
+            self.pending_reload = None;
+            return;
         }
 
-        self.instances.par_iter_mut().for_each(|world| {
-            world.generation = g;
-            for _ in 0..env_steps {
-                world.step();
+        match load_program(&text, &self.sim.program) {
+            Err(errors) => {
+                println!("Reload failed:");
+                for e in &errors {
+                    println!("  - {}", e);
+                }
+                self.reload_errors = errors;
             }
-            //capture final state as a snapshot
-            if world.record_history {
-                let mut final_snapshot = Vec::new();
-                for ind in &world.individuals {
-                    let cloned_ind = ind.deep_clone();
-                    final_snapshot.push(cloned_ind);
+            Ok(program) => {
+                self.reload_errors.clear();
+                if only_visualize_changed(&self.source, &text) {
+                    println!("Reloaded VISUALIZE");
+                    self.sim.replace_program(Arc::new(program));
+                    self.source = text;
+                    self.pending_reload = None;
+                } else {
+                    println!("Source changed, restart evolution to apply it");
+                    self.pending_reload = Some((program, text));
                 }
-                world.history.push(final_snapshot);
             }
-            world.calculate_total_fitness();
-        });
-
-        //sort by fitness
-        let mut indices: Vec<usize> = Vec::new();
-        for i in 0..self.instances.len() {
-            indices.push(i);
-        }
-        indices.sort_by_key(|&i| -self.instances[i].fitness);
-        
-        let mut sorted_instances = Vec::new();
-        for &i in &indices {
-            sorted_instances.push(self.instances[i].take());
         }
-        self.instances = sorted_instances;
-        
-        //extract history from the best instance
-        let raw_history = std::mem::take(&mut self.instances[0].history);
-        let mut best_history = Vec::new();
-        for step_individuals in raw_history {
-            let step_snapshot = snapshot_individuals(&step_individuals, &self.program);
-            //break reference cycles in the original heavy data
-            for ind in step_individuals {
-                ind.env.write().unwrap().store.clear();
-            }
-            best_history.push(step_snapshot);
+    }
+
+    //throw the history away and run the reloaded program from generation 0
+    pub fn restart_with_reload(&mut self) {
+        if let Some((program, text)) = self.pending_reload.take() {
+            self.world_width = program.env_width;
+            self.world_height = program.env_height;
+            self.sim.restart(Arc::new(program));
+            self.source = text;
+            self.current_gen_idx = 0;
+            self.current_step_idx = 1;
+            self.running = false;
+            self.playing = false;
+            self.inspected = None;
         }
-        
-        //clear history from other instances
-        clear_world_history(&mut self.instances);
-
-        // record statistics
-        let mut total_fitness: i32 = 0;
-        for w in &self.instances {
-            total_fitness += w.fitness;
-        }
-        let avg = total_fitness / self.num_instances.max(1);
-        let best = self.instances[0].fitness;
-        if best > self.global_best_fitness {
-            self.global_best_fitness = best;
-        }
-
-        //store snapshot for visualization
-        let snapshot = GenerationSnapshot {
-            avg_fitness: avg,
-            best_fitness: best,
-            individuals: snapshot_individuals(&self.instances[0].individuals, &self.program),
-            step_history: best_history,
-        };
-        
+    }
+
+    //run one generation of evolution and keep the view in sync
+    pub fn run_generation(&mut self) {
         //track if it was at the end before adding new history
-        let was_at_end = self.current_gen_idx >= self.history.len().saturating_sub(1);
-        
-        self.history.push(snapshot);
-        
-        //limit history to prevent memory leak
-        // rip my laptop learned from experience </3
-        if self.history.len() > 100 {
-            let removed = self.history.remove(0);
-            clear_snapshot_memory(&removed);
-            if self.current_gen_idx > 0 {
-                self.current_gen_idx -= 1;
+        let was_at_end = self.current_gen_idx >= self.sim.history.len().saturating_sub(1);
+
+        let report = match self.sim.run_generation() {
+            Some(report) => report,
+            None => {
+                self.running = false;
+                return;
             }
+        };
+        println!("{}", report);
+
+        if report.history_trimmed && self.current_gen_idx > 0 {
+            self.current_gen_idx -= 1;
         }
-        
+
         //auto-follow-> if viewing latest, move to new latest
         if was_at_end {
-            self.current_gen_idx = self.history.len().saturating_sub(1);
+            self.current_gen_idx = self.sim.history.len().saturating_sub(1);
             self.current_step_idx = 0;
         }
-
-        let duration = start.elapsed();
-        println!("[Gen {}] Avg: {}, Best: {} (took {:?})", g, avg, best, duration);
-
-        //create next generation
-        self.instances = create_next_generation(
-            &mut self.instances,
-            &self.program,
-            self.num_instances,
-            self.current_gen,
-        );
     }
 
     //reset to initial state
     pub fn reset(&mut self) {
-        self.current_gen = 0;
-        self.history.clear();
+        self.sim.reset();
         self.current_gen_idx = 0;
         self.current_step_idx = 1;
         self.running = false;
-        self.global_best_fitness = 0;
-        
-        let mut new_instances = Vec::new();
-        for i in 0..self.num_instances {
-            let mut w = World::new(self.program.clone(), i);
-            w.spawn();
-            new_instances.push(w);
+        self.playing = false;
+    }
+
+    //replay the shown generation from its first step
+    pub fn start_playback(&mut self) {
+        self.current_step_idx = 0;
+        self.playing = true;
+        self.last_frame = Instant::now();
+    }
+
+    //move the replay one step forward once a frame's time has passed
+    fn advance_playback(&mut self, ctx: &egui::Context) {
+        let steps = match self.sim.history.get(self.current_gen_idx) {
+            Some(snapshot) => snapshot.step_history.len() as i32,
+            None => 0,
+        };
+        if steps == 0 {
+            self.playing = false;
+            return;
         }
-        self.instances = new_instances;
+
+        let interval = Duration::from_secs_f32(1.0 / self.fps.max(1.0));
+        if self.last_frame.elapsed() >= interval {
+            self.last_frame = Instant::now();
+            if self.current_step_idx + 1 < steps {
+                self.current_step_idx += 1;
+            } else if self.loop_playback && !(self.running && self.auto_play) {
+                self.current_step_idx = 0;
+            } else {
+                //auto-play moves on to the next generation from here
+                self.playing = false;
+            }
+        }
+        ctx.request_repaint_after(interval);
     }
 }
 
 //egui application - ui rendering
 
 impl eframe::App for SimApp {
+    #[allow(clippy::collapsible_if)]
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if self.running {
+        if self.watcher.is_some() {
+            self.check_source();
+            ctx.request_repaint_after(POLL_INTERVAL);
+        }
+
+        if self.playing {
+            self.advance_playback(ctx);
+        }
+
+        //with auto-play the next generation waits until the replay is over
+        if self.running && !(self.auto_play && self.playing) {
             self.run_generation();
+            if self.auto_play && !self.sim.history.is_empty() {
+                self.current_gen_idx = self.sim.history.len() - 1;
+                self.start_playback();
+            }
             ctx.request_repaint();
         }
 
+        if !self.reload_errors.is_empty() || self.pending_reload.is_some() {
+            egui::TopBottomPanel::top("reload_panel").show(ctx, |ui| {
+                self.render_reload(ui);
+            });
+        }
+
+        egui::SidePanel::right("fitness_panel")
+            .resizable(true)
+            .default_width(350.0)
+            .show(ctx, |ui| {
+                self.render_fitness_plot(ui);
+            });
+
+        if self.inspected.is_some() {
+            egui::SidePanel::left("inspector_panel")
+                .resizable(true)
+                .default_width(260.0)
+                .show(ctx, |ui| {
+                    self.render_inspector(ui);
+                });
+        }
+
+        if self.sim.program.profile {
+            egui::TopBottomPanel::bottom("profile_panel")
+                .resizable(true)
+                .default_height(150.0)
+                .show(ctx, |ui| {
+                    self.render_profile(ui);
+                });
+        }
+
+        if self.editor.open {
+            let mut open = true;
+            egui::Window::new("Source")
+                .open(&mut open)
+                .default_size([560.0, 620.0])
+                .show(ctx, |ui| {
+                    self.render_editor(ui);
+                });
+            self.editor.open &= open;
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if self.running {
                 ui.ctx().request_repaint();
@@ -219,11 +290,17 @@ impl eframe::App for SimApp {
                 if ui.button("Reset").clicked() {
                     self.reset();
                 }
+                if ui.button("Editor").clicked() {
+                    if !self.editor.open {
+                        self.editor.load(&self.source);
+                    }
+                    self.editor.open = !self.editor.open;
+                }
                 if ui.button("<- Prev").clicked() && self.current_gen_idx > 0 {
                     self.current_gen_idx -= 1;
                 }
-                ui.label(format!("Gen {} / {}", self.current_gen_idx + 1, self.history.len()));
-                if ui.button("Next ->").clicked() && self.current_gen_idx < self.history.len().saturating_sub(1) {
+                ui.label(format!("Gen {} / {}", self.current_gen_idx + 1, self.sim.history.len()));
+                if ui.button("Next ->").clicked() && self.current_gen_idx < self.sim.history.len().saturating_sub(1) {
                     self.current_gen_idx += 1;
                 }
             });
@@ -231,11 +308,13 @@ impl eframe::App for SimApp {
             //status display
             ui.horizontal(|ui| {
                 ui.label("Status:");
-                if self.num_instances == 0 {
+                if let Some(e) = &self.sim.error {
+                    ui.colored_label(egui::Color32::RED, e.to_string());
+                } else if self.sim.num_instances == 0 {
                     ui.colored_label(egui::Color32::RED, "Error: No instances");
-                } else if self.num_generations == 0 {
+                } else if self.sim.num_generations == 0 {
                     ui.colored_label(egui::Color32::YELLOW, "Warning: No generations");
-                } else if self.current_gen >= self.num_generations {
+                } else if self.sim.is_finished() {
                     ui.colored_label(egui::Color32::GREEN, "Finished");
                 } else if self.running {
                     ui.colored_label(egui::Color32::LIGHT_BLUE, "Running (Auto-following)...");
@@ -244,19 +323,19 @@ impl eframe::App for SimApp {
                 }
             });
 
-            if self.history.is_empty() {
+            if self.sim.history.is_empty() {
                 ui.label("Press Start or Step to begin evolution.");
                 return;
             }
 
-            let snapshot = &self.history[self.current_gen_idx];
+            let snapshot = &self.sim.history[self.current_gen_idx];
             ui.label(format!(
                 "Avg: {}, Gen Best: {}, Global Best: {}",
-                snapshot.avg_fitness, snapshot.best_fitness, self.global_best_fitness
+                snapshot.avg_fitness, snapshot.best_fitness, self.sim.global_best_fitness
             ));
 
             //generation slider
-            if ui.add(egui::Slider::new(&mut self.current_gen_idx, 0..=self.history.len().saturating_sub(1))
+            if ui.add(egui::Slider::new(&mut self.current_gen_idx, 0..=self.sim.history.len().saturating_sub(1))
                 .text("View Gen")).changed() 
             {
                 self.current_step_idx = 0;
@@ -268,10 +347,54 @@ impl eframe::App for SimApp {
                 ui.add(egui::Slider::new(&mut self.current_step_idx, 0..=max_steps).text("Step"));
             }
 
+            //replay controls
+            let steps = snapshot.step_history.len() as i32;
+            ui.horizontal(|ui| {
+                if ui.button(if self.playing { "Pause" } else { "Play" }).clicked() {
+                    if self.playing {
+                        self.playing = false;
+                    } else if self.current_step_idx + 1 >= steps {
+                        self.start_playback();
+                    } else {
+                        self.playing = true;
+                        self.last_frame = Instant::now();
+                    }
+                }
+                ui.checkbox(&mut self.loop_playback, "Loop");
+                ui.add(egui::Slider::new(&mut self.fps, 1.0..=60.0).text("FPS"));
+                ui.checkbox(&mut self.auto_play, "Auto-play new generations");
+            });
+
+            //export the shown generation's replay
+            ui.horizontal(|ui| {
+                ui.label("Export to:");
+                ui.text_edit_singleline(&mut self.export_path);
+                if ui.button("Export").clicked() {
+                    let snapshot = &self.sim.history[self.current_gen_idx];
+                    let status = match export(&self.sim.program, snapshot, self.export_path.trim(), self.fps) {
+                        Ok(message) => message,
+                        Err(e) => format!("Export failed: {}", e),
+                    };
+                    println!("{}", status);
+                    self.export_status = Some(status);
+                }
+                if let Some(status) = &self.export_status {
+                    ui.label(status.as_str());
+                }
+            });
+
+            //canvas view: drag to pan, scroll to zoom
+            ui.horizontal(|ui| {
+                ui.label(format!("Zoom: {:.0}%", self.zoom * 100.0));
+                if ui.button("Reset view").clicked() {
+                    self.reset_view();
+                }
+            });
+
             ui.separator();
 
             //render visualization
-            self.render_visualization(ui, snapshot);
+            self.render_visualization(ui);
         });
     }
 }
@@ -279,101 +402,420 @@ impl eframe::App for SimApp {
 //visualization rendering
 
 impl SimApp {
-    fn validate_can_run(&self) -> bool {
-        if self.num_instances == 0 {
-            println!("Error: No instances defined in EVOLVE block.");
-            return false;
+    //errors of a failed reload, or the offer to restart with the changed source
+    fn render_reload(&mut self, ui: &mut egui::Ui) {
+        let path = self.watcher.as_ref().map_or(String::new(), |w| w.path.clone());
+        if !self.reload_errors.is_empty() {
+            ui.colored_label(egui::Color32::RED, format!("{} has errors, still running the previous version:", path));
+            for e in &self.reload_errors {
+                ui.colored_label(egui::Color32::RED, format!("  {}", e));
+            }
+        } else if self.pending_reload.is_some() {
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::YELLOW, format!("{} changed outside VISUALIZE.", path));
+                if ui.button("Restart evolution").clicked() {
+                    self.restart_with_reload();
+                }
+                if ui.button("Ignore").clicked() {
+                    self.pending_reload = None;
+                }
+            });
+        }
+    }
+
+    //edit the program in place. Apply rebuilds it and starts evolution over
+    fn render_editor(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let clean = !has_errors(&self.editor.diagnostics);
+            if ui.add_enabled(clean, egui::Button::new("Apply")).clicked() {
+                match load_program(&self.editor.text, &self.sim.program) {
+                    Ok(program) => {
+                        println!("Applied the edited source");
+                        self.pending_reload = Some((program, self.editor.text.clone()));
+                        self.restart_with_reload();
+                        self.reload_errors.clear();
+                    }
+                    Err(errors) => {
+                        self.editor.diagnostics = errors;
+                    }
+                }
+            }
+            if ui.button("Revert").clicked() {
+                self.editor.load(&self.source);
+            }
+            if self.editor.text != self.source {
+                ui.label("(modified)");
+            }
+        });
+
+        for d in &self.editor.diagnostics {
+            let color = if d.is_error() { egui::Color32::RED } else { egui::Color32::YELLOW };
+            ui.colored_label(color, d.to_string());
         }
-        if self.num_generations == 0 {
-            println!("Error: No generations defined in EVOLVE block.");
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if self.editor.show(ui) {
+                self.editor.check();
+            }
+        });
+    }
+
+    fn validate_can_run(&self) -> bool {
+        if let Err(e) = self.sim.validate() {
+            println!("Error: {}", e);
             return false;
         }
         true
     }
 
-    fn render_visualization(&self, ui: &mut egui::Ui, snapshot: &GenerationSnapshot) {
+    //chart best/avg/worst fitness per generation plus any custom series.
+    //clicking the chart jumps to the nearest generation
+    fn render_fitness_plot(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Fitness");
+
+        //custom series: average of a species property in the best instance
+        ui.horizontal(|ui| {
+            ui.label("Series:");
+            let edit = ui.text_edit_singleline(&mut self.new_series);
+            let submitted = edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Add").clicked() || submitted {
+                let name = self.new_series.trim().to_string();
+                if !name.is_empty() && !self.plot_series.contains(&name) {
+                    self.plot_series.push(name);
+                }
+                self.new_series.clear();
+            }
+        });
+        let mut removed = None;
+        ui.horizontal_wrapped(|ui| {
+            for (i, name) in self.plot_series.iter().enumerate() {
+                if ui.small_button(format!("{} x", name)).clicked() {
+                    removed = Some(i);
+                }
+            }
+        });
+        if let Some(i) = removed {
+            self.plot_series.remove(i);
+        }
+
+        let history = &self.sim.history;
+        let mut best = Vec::new();
+        let mut avg = Vec::new();
+        let mut worst = Vec::new();
+        for snapshot in history {
+            let g = snapshot.generation as f64;
+            best.push([g, snapshot.best_fitness as f64]);
+            avg.push([g, snapshot.avg_fitness as f64]);
+            worst.push([g, snapshot.worst_fitness as f64]);
+        }
+
+        let mut custom = Vec::new();
+        for name in &self.plot_series {
+            let mut points = Vec::new();
+            for snapshot in history {
+                if let Some(v) = property_average(&snapshot.individuals, name) {
+                    points.push([snapshot.generation as f64, v]);
+                }
+            }
+            custom.push((name.clone(), points));
+        }
+
+        let selected = history.get(self.current_gen_idx).map(|s| s.generation as f64);
+        let response = Plot::new("fitness_plot")
+            .legend(Legend::default())
+            .x_axis_label("Generation")
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(PlotPoints::from(best)).name("Best"));
+                plot_ui.line(Line::new(PlotPoints::from(avg)).name("Average"));
+                plot_ui.line(Line::new(PlotPoints::from(worst)).name("Worst"));
+                for (name, points) in custom {
+                    plot_ui.line(Line::new(PlotPoints::from(points)).name(name));
+                }
+                if let Some(g) = selected {
+                    plot_ui.vline(VLine::new(g).color(egui::Color32::GRAY));
+                }
+                plot_ui.pointer_coordinate()
+            });
+
+        if response.response.clicked()
+            && let Some(pointer) = response.inner {
+            //find the closest generation still in history
+            let mut closest = None;
+            let mut closest_dist = f64::MAX;
+            for (i, snapshot) in history.iter().enumerate() {
+                let dist = (snapshot.generation as f64 - pointer.x).abs();
+                if dist < closest_dist {
+                    closest_dist = dist;
+                    closest = Some(i);
+                }
+            }
+            if let Some(i) = closest {
+                self.current_gen_idx = i;
+                self.current_step_idx = 0;
+            }
+        }
+    }
+
+    //time and calls per routine and block, summed over all instances
+    fn render_profile(&self, ui: &mut egui::Ui) {
+        ui.heading("Profile");
+        let profile = &self.sim.profile;
+        if profile.is_empty() {
+            ui.label("Nothing recorded yet.");
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("profile_grid").striped(true).show(ui, |ui| {
+                ui.strong("Section");
+                ui.strong("Total");
+                ui.strong("Share");
+                ui.strong("Calls");
+                ui.strong("Per call");
+                ui.end_row();
+                for (section, timing) in profile.rows() {
+                    ui.label(section);
+                    ui.label(format!("{:.2?}", timing.total));
+                    ui.label(format!("{:.1}%", profile.share(&timing)));
+                    ui.label(timing.calls.to_string());
+                    ui.label(format!("{:.2?}", timing.per_call()));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    //individuals shown for the selected generation and step
+    fn shown_individuals<'a>(&self, snapshot: &'a GenerationSnapshot) -> &'a [Individual] {
+        if !snapshot.step_history.is_empty() {
+            let idx = (self.current_step_idx as usize).min(snapshot.step_history.len().saturating_sub(1));
+            &snapshot.step_history[idx]
+        } else {
+            &snapshot.individuals
+        }
+    }
+
+    //size of one world cell on the canvas at zoom 1
+    fn cell_size(&self, rect: egui::Rect) -> egui::Vec2 {
+        egui::vec2(rect.width() / self.world_width.max(1) as f32, rect.height() / self.world_height.max(1) as f32)
+    }
+
+    //canvas point (pixels at zoom 1) to screen, through pan and zoom
+    fn to_screen(&self, rect: egui::Rect, x: f32, y: f32) -> egui::Pos2 {
+        rect.min + self.pan + egui::vec2(x, y) * self.zoom
+    }
+
+    //screen position back to a canvas point, undoing pan and zoom
+    fn to_canvas(&self, rect: egui::Rect, pos: egui::Pos2) -> egui::Vec2 {
+        (pos - rect.min - self.pan) / self.zoom
+    }
+
+    //the canvas is the whole world, so a pixel maps back to a cell by the world size
+    fn canvas_to_cell(&self, rect: egui::Rect, pos: egui::Pos2) -> (i32, i32) {
+        let cell = self.cell_size(rect);
+        let p = self.to_canvas(rect, pos);
+        ((p.x / cell.x).floor() as i32, (p.y / cell.y).floor() as i32)
+    }
+
+    //drag pans, the scroll wheel zooms around the pointer
+    fn handle_view_input(&mut self, ui: &egui::Ui, rect: egui::Rect, response: &egui::Response) {
+        if response.dragged() {
+            self.pan += response.drag_delta();
+        }
+        if !response.hovered() {
+            return;
+        }
+        let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+        if scroll == 0.0 {
+            return;
+        }
+        if let Some(pointer) = response.hover_pos() {
+            //keep the point under the pointer where it is
+            let anchor = self.to_canvas(rect, pointer);
+            self.zoom = (self.zoom * (scroll * 0.002).exp()).clamp(0.25, 20.0);
+            self.pan = pointer - rect.min - anchor * self.zoom;
+        }
+    }
+
+    pub fn reset_view(&mut self) {
+        self.zoom = 1.0;
+        self.pan = egui::Vec2::ZERO;
+    }
+
+    fn render_visualization(&mut self, ui: &mut egui::Ui) {
+        let snapshot = &self.sim.history[self.current_gen_idx];
+
         //clear previous draw commands
         DRAW_COMMANDS.with(|cmds| cmds.borrow_mut().clear());
 
         //execute VISUALIZE block
-        if !self.program.visualize_block.is_empty() {
-            self.execute_visualize_block(snapshot);
+        if !self.sim.program.visualize_block.is_empty() {
+            let start = self.sim.program.profile.then(Instant::now);
+            if let Err(e) = self.execute_visualize_block(snapshot) {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+            }
+            self.sim.profile.record("VISUALIZE", start);
         }
 
         //draw canvas
         let size = 600.0;
-        let (rect, _) = ui.allocate_at_least(egui::vec2(size, size), egui::Sense::hover());
-        let painter = ui.painter();
+        let (rect, response) = ui.allocate_at_least(egui::vec2(size, size), egui::Sense::click_and_drag());
+        self.handle_view_input(ui, rect, &response);
+        let snapshot = &self.sim.history[self.current_gen_idx];
+        //clip so zoomed in drawings stay on the canvas
+        let painter = ui.painter_at(rect);
         
         painter.rect_filled(rect, 0.0, egui::Color32::from_rgb(20, 20, 20));
+
+        //VISUALIZE coordinates are pixels or cells, depending on the environment
+        let unit = match self.sim.program.env_coordinates {
+            Coordinates::Pixels => egui::vec2(1.0, 1.0),
+            Coordinates::Cells => self.cell_size(rect),
+        };
+        let radius_unit = unit.x.min(unit.y) * self.zoom;
         
         //draw all commands
         DRAW_COMMANDS.with(|cmds| {
             for cmd in cmds.borrow().iter() {
                 match cmd {
                     DrawCmd::Rect { x, y, w, h, r, g, b } => {
-                        let min = rect.min + egui::vec2(*x, *y);
+                        let min = self.to_screen(rect, x * unit.x, y * unit.y);
                         painter.rect_filled(
-                            egui::Rect::from_min_size(min, egui::vec2(*w, *h)),
+                            egui::Rect::from_min_size(min, egui::vec2(w * unit.x, h * unit.y) * self.zoom),
                             0.0,
                             egui::Color32::from_rgb(*r, *g, *b),
                         );
                     }
                     DrawCmd::Line { x1, y1, x2, y2, r, g, b, thickness } => {
                         painter.line_segment(
-                            [rect.min + egui::vec2(*x1, *y1), rect.min + egui::vec2(*x2, *y2)],
-                            egui::Stroke::new(*thickness, egui::Color32::from_rgb(*r, *g, *b)),
+                            [self.to_screen(rect, x1 * unit.x, y1 * unit.y), self.to_screen(rect, x2 * unit.x, y2 * unit.y)],
+                            egui::Stroke::new(thickness * self.zoom, egui::Color32::from_rgb(*r, *g, *b)),
                         );
                     }
                     DrawCmd::Circle { x, y, radius, r, g, b } => {
                         painter.circle_filled(
-                            rect.min + egui::vec2(*x, *y),
-                            *radius,
+                            self.to_screen(rect, x * unit.x, y * unit.y),
+                            radius * radius_unit,
                             egui::Color32::from_rgb(*r, *g, *b),
                         );
                     }
                 }
             }
         });
-    }
 
-    fn execute_visualize_block(&self, snapshot: &GenerationSnapshot) {
-        WORLD_DIMENSIONS.with(|d| *d.borrow_mut() = (self.world_width, self.world_height));
-        
-        let viz_env = Environment::new();
-        {
-            let mut env_mut = viz_env.write().unwrap();
-            env_mut.store.insert("width".to_string(), Value::Int(self.world_width));
-            env_mut.store.insert("height".to_string(), Value::Int(self.world_height));
+        //outline whoever the inspector follows
+        let individuals = self.shown_individuals(snapshot);
+        if let Some(inspection) = &self.inspected {
+            let cell = self.cell_size(rect);
+            for ind in individuals {
+                if !inspection.ids.contains(&ind.id) {
+                    continue;
+                }
+                if let Some((x, y)) = position(&ind.env.read().unwrap()) {
+                    let min = self.to_screen(rect, x as f32 * cell.x, y as f32 * cell.y);
+                    painter.rect_stroke(
+                        egui::Rect::from_min_size(min, cell * self.zoom),
+                        0.0,
+                        egui::Stroke::new(2.0, egui::Color32::YELLOW),
+                    );
+                }
+            }
         }
-        
-        //use the snapshot directly
-        let viz_individuals = if !snapshot.step_history.is_empty() {
-            let idx = (self.current_step_idx as usize).min(snapshot.step_history.len().saturating_sub(1));
-            &snapshot.step_history[idx]
-        } else {
-            &snapshot.individuals
-        };
 
-        // set up grid cache for visualization
-        let mut grid_map = HashMap::new();
-        for ind in viz_individuals {
-            let env_b = ind.env.read().unwrap();
-            let store = &env_b.store;
-            if let Some(Value::Int(x)) = store.get("x") {
-                if let Some(Value::Int(y)) = store.get("y") {
-                    grid_map.insert((*x, *y), ind.env.clone());
+        //clicking a cell inspects everyone standing on it
+        if response.clicked()
+            && let Some(pos) = response.interact_pointer_pos() {
+            let cell = self.canvas_to_cell(rect, pos);
+            let mut ids = Vec::new();
+            for ind in individuals {
+                if position(&ind.env.read().unwrap()) == Some(cell) {
+                    ids.push(ind.id);
                 }
             }
+            self.inspected = Some(Inspection { cell, ids });
+        }
+    }
+
+    //species and every stored property of the inspected individuals in the shown step
+    fn render_inspector(&mut self, ui: &mut egui::Ui) {
+        let mut close = false;
+        ui.horizontal(|ui| {
+            ui.heading("Inspector");
+            close = ui.button("Close").clicked();
+        });
+        let inspection = match &self.inspected {
+            Some(inspection) => inspection,
+            None => return,
+        };
+        ui.label(format!("Clicked cell ({}, {})", inspection.cell.0, inspection.cell.1));
+        ui.separator();
+
+        if inspection.ids.is_empty() {
+            ui.label("Nobody was standing here.");
         }
-        GRID_CACHE.with(|cache| *cache.borrow_mut() = Some(grid_map));
+        let individuals = match self.sim.history.get(self.current_gen_idx) {
+            Some(snapshot) => self.shown_individuals(snapshot),
+            None => &[],
+        };
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for id in &inspection.ids {
+                let ind = match individuals.iter().find(|ind| ind.id == *id) {
+                    Some(ind) => ind,
+                    None => {
+                        ui.label(format!("#{} is not in this step", id));
+                        continue;
+                    }
+                };
+                let env = ind.env.read().unwrap();
+                egui::CollapsingHeader::new(format!("#{} {}", id, ind.species))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        let mut names: Vec<&String> = env.store.keys().filter(|k| k.as_str() != "self").collect();
+                        names.sort();
+                        for name in names {
+                            match &env.store[name] {
+                                //lists get one row per element
+                                Value::List(list) => {
+                                    let list = list.read().unwrap();
+                                    egui::CollapsingHeader::new(format!("{} ({} items)", name, list.len()))
+                                        .id_source((id, name))
+                                        .default_open(true)
+                                        .show(ui, |ui| {
+                                            for (i, item) in list.iter().enumerate() {
+                                                ui.label(format!("[{}] {}", i, item.describe()));
+                                            }
+                                        });
+                                }
+                                value => { ui.label(format!("{}: {}", name, value.describe())); }
+                            }
+                        }
+                    });
+            }
+        });
 
-        //execute visualize commands
-        let mut spawner = Vec::new();
-        for cmd in &self.program.visualize_block {
-            cmd.execute(viz_env.clone(), viz_individuals, &mut spawner, &self.program);
+        if close {
+            self.inspected = None;
         }
+    }
+
+    fn execute_visualize_block(&self, snapshot: &GenerationSnapshot) -> Result<(), RuntimeError> {
+        run_visualize(&self.sim.program, self.shown_individuals(snapshot))
+    }
+}
 
-        GRID_CACHE.with(|cache| *cache.borrow_mut() = None);
+//average of a numeric property over a set of individuals,
+//None if nobody has it (so the series just skips that generation)
+fn property_average(individuals: &[Individual], name: &str) -> Option<f64> {
+    let mut total = 0.0;
+    let mut count = 0;
+    for ind in individuals {
+        if let Some(v @ (Value::Int(_) | Value::Float(_) | Value::Bool(_))) = ind.env.read().unwrap().store.get(name) {
+            total += v.to_float();
+            count += 1;
+        }
     }
+    if count == 0 { None } else { Some(total / count as f64) }
 }
@@ -1,12 +1,17 @@
+use crate::diagnostic::Diagnostic;
+
 #[derive(Debug, PartialEq, Clone)]
 //just recognizes tokens nothing notable or complicated
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenKind {
     Environment, Species, Evolve, Mutate, Fitness, Visualize,
-    Routine, Spawn, At, Random,
+    Routine, Function, Spawn, Die, At, Random,
     If, Else, While, For, In, Return, Print,
     True, False,
     Identifier(String),
     Number(i32),
+    WideNumber(u64), //whole number too big for Number, only a seed can be this large
+    Float(f64),
     StringLiteral(String),
     LBrace, RBrace, LParen, RParen, LBracket, RBracket,
     Colon, SemiColon, Comma, Equal, Plus, Minus, Star, Slash,
@@ -20,15 +25,23 @@ pub struct Token {
     pub kind: TokenKind,
     pub line: usize,
     pub col: usize,
+    pub end_col: usize, //column right after the token
 }
 
 pub fn lexer(input: &str) -> Vec<Token> {
+    lex(input).0
+}
+
+//tokens plus warnings about characters that were skipped
+pub fn lex(input: &str) -> (Vec<Token>, Vec<Diagnostic>) {
     let mut tokens = Vec::new();
+    let mut diagnostics = Vec::new();
     let mut chars = input.chars().peekable();
     let mut line = 1;
     let mut col = 1;
 
     while let Some(&c) = chars.peek() {
+        let before = tokens.len();
         match c {
             ' ' | '\r' | '\t' => { 
                 col += 1;
@@ -43,44 +56,52 @@ pub fn lexer(input: &str) -> Vec<Token> {
                 let start_col = col;
                 chars.next(); col += 1;
                 if chars.peek() == Some(&'=') {
-                    tokens.push(Token { kind: TokenKind::NotEqual, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::NotEqual, line, col: start_col, end_col: 0 });
                     chars.next(); col += 1;
+                } else {
+                    diagnostics.push(Diagnostic::warning("Unexpected '!' was ignored").at(line, start_col, col)
+                        .note("there is no ! operator, did you mean != ?"));
                 }
             }
             '"' => {
                 let start_col = col;
                 chars.next(); col += 1;
                 let mut s = String::new();
+                let mut closed = false;
                 while let Some(&cc) = chars.peek() {
-                    if cc == '"' { chars.next(); col += 1; break; }
+                    if cc == '"' { chars.next(); col += 1; closed = true; break; }
                     s.push(cc);
                     chars.next(); col += 1;
                 }
-                tokens.push(Token { kind: TokenKind::StringLiteral(s), line, col: start_col });
+                if !closed {
+                    diagnostics.push(Diagnostic::warning("Unterminated string, it runs to the end of the file")
+                        .at(line, start_col, start_col + 1));
+                }
+                tokens.push(Token { kind: TokenKind::StringLiteral(s), line, col: start_col, end_col: 0 });
             }
-            '{' => { tokens.push(Token { kind: TokenKind::LBrace, line, col }); chars.next(); col += 1; }
-            '}' => { tokens.push(Token { kind: TokenKind::RBrace, line, col }); chars.next(); col += 1; }
-            '(' => { tokens.push(Token { kind: TokenKind::LParen, line, col }); chars.next(); col += 1; }
-            ')' => { tokens.push(Token { kind: TokenKind::RParen, line, col }); chars.next(); col += 1; }
-            '[' => { tokens.push(Token { kind: TokenKind::LBracket, line, col }); chars.next(); col += 1; }
-            ']' => { tokens.push(Token { kind: TokenKind::RBracket, line, col }); chars.next(); col += 1; }
-            ':' => { tokens.push(Token { kind: TokenKind::Colon, line, col }); chars.next(); col += 1; }
-            ';' => { tokens.push(Token { kind: TokenKind::SemiColon, line, col }); chars.next(); col += 1; }
-            ',' => { tokens.push(Token { kind: TokenKind::Comma, line, col }); chars.next(); col += 1; }
-            '.' => { tokens.push(Token { kind: TokenKind::Dot, line, col }); chars.next(); col += 1; }
+            '{' => { tokens.push(Token { kind: TokenKind::LBrace, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '}' => { tokens.push(Token { kind: TokenKind::RBrace, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '(' => { tokens.push(Token { kind: TokenKind::LParen, line, col, end_col: 0 }); chars.next(); col += 1; }
+            ')' => { tokens.push(Token { kind: TokenKind::RParen, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '[' => { tokens.push(Token { kind: TokenKind::LBracket, line, col, end_col: 0 }); chars.next(); col += 1; }
+            ']' => { tokens.push(Token { kind: TokenKind::RBracket, line, col, end_col: 0 }); chars.next(); col += 1; }
+            ':' => { tokens.push(Token { kind: TokenKind::Colon, line, col, end_col: 0 }); chars.next(); col += 1; }
+            ';' => { tokens.push(Token { kind: TokenKind::SemiColon, line, col, end_col: 0 }); chars.next(); col += 1; }
+            ',' => { tokens.push(Token { kind: TokenKind::Comma, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '.' => { tokens.push(Token { kind: TokenKind::Dot, line, col, end_col: 0 }); chars.next(); col += 1; }
             '=' => {
                 let start_col = col;
                 chars.next(); col += 1;
                 if chars.peek() == Some(&'=') {
-                    tokens.push(Token { kind: TokenKind::DoubleEqual, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::DoubleEqual, line, col: start_col, end_col: 0 });
                     chars.next(); col += 1;
                 } else {
-                    tokens.push(Token { kind: TokenKind::Equal, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::Equal, line, col: start_col, end_col: 0 });
                 }
             }
-            '+' => { tokens.push(Token { kind: TokenKind::Plus, line, col }); chars.next(); col += 1; }
-            '-' => { tokens.push(Token { kind: TokenKind::Minus, line, col }); chars.next(); col += 1; }
-            '*' => { tokens.push(Token { kind: TokenKind::Star, line, col }); chars.next(); col += 1; }
+            '+' => { tokens.push(Token { kind: TokenKind::Plus, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '-' => { tokens.push(Token { kind: TokenKind::Minus, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '*' => { tokens.push(Token { kind: TokenKind::Star, line, col, end_col: 0 }); chars.next(); col += 1; }
             '/' => {
                 let start_col = col;
                 chars.next(); col += 1;
@@ -90,43 +111,49 @@ pub fn lexer(input: &str) -> Vec<Token> {
                         chars.next(); col += 1;
                     }
                 } else {
-                    tokens.push(Token { kind: TokenKind::Slash, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::Slash, line, col: start_col, end_col: 0 });
                 }
             }
             '>' => {
                 let start_col = col;
                 chars.next(); col += 1;
                 if chars.peek() == Some(&'=') {
-                    tokens.push(Token { kind: TokenKind::GreaterEqual, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::GreaterEqual, line, col: start_col, end_col: 0 });
                     chars.next(); col += 1;
                 } else {
-                    tokens.push(Token { kind: TokenKind::Greater, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::Greater, line, col: start_col, end_col: 0 });
                 }
             }
             '<' => {
                 let start_col = col;
                 chars.next(); col += 1;
                 if chars.peek() == Some(&'=') {
-                    tokens.push(Token { kind: TokenKind::LessEqual, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::LessEqual, line, col: start_col, end_col: 0 });
                     chars.next(); col += 1;
                 } else {
-                    tokens.push(Token { kind: TokenKind::Less, line, col: start_col });
+                    tokens.push(Token { kind: TokenKind::Less, line, col: start_col, end_col: 0 });
                 }
             }
-            '%' => { tokens.push(Token { kind: TokenKind::Percent, line, col }); chars.next(); col += 1; }
-            '@' => { tokens.push(Token { kind: TokenKind::At, line, col }); chars.next(); col += 1; }
+            '%' => { tokens.push(Token { kind: TokenKind::Percent, line, col, end_col: 0 }); chars.next(); col += 1; }
+            '@' => { tokens.push(Token { kind: TokenKind::At, line, col, end_col: 0 }); chars.next(); col += 1; }
             '|' => {
                 chars.next(); col += 1;
                 if chars.peek() == Some(&'|') {
-                    tokens.push(Token { kind: TokenKind::Or, line, col: col - 1 });
+                    tokens.push(Token { kind: TokenKind::Or, line, col: col - 1, end_col: 0 });
                     chars.next(); col += 1;
+                } else {
+                    diagnostics.push(Diagnostic::warning("Unexpected '|' was ignored").at(line, col - 1, col)
+                        .note("did you mean || ?"));
                 }
             }
             '&' => {
                 chars.next(); col += 1;
                 if chars.peek() == Some(&'&') {
-                    tokens.push(Token { kind: TokenKind::And, line, col: col - 1 });
+                    tokens.push(Token { kind: TokenKind::And, line, col: col - 1, end_col: 0 });
                     chars.next(); col += 1;
+                } else {
+                    diagnostics.push(Diagnostic::warning("Unexpected '&' was ignored").at(line, col - 1, col)
+                        .note("did you mean && ?"));
                 }
             }
             '0'..='9' => {
@@ -138,7 +165,28 @@ pub fn lexer(input: &str) -> Vec<Token> {
                         chars.next(); col += 1;
                     } else { break; }
                 }
-                tokens.push(Token { kind: TokenKind::Number(num_str.parse().unwrap()), line, col: start_col });
+                //a dot followed by a digit makes it a decimal: 0.25
+                let mut lookahead = chars.clone();
+                lookahead.next();
+                if chars.peek() == Some(&'.') && lookahead.peek().is_some_and(|d| d.is_ascii_digit()) {
+                    num_str.push('.');
+                    chars.next(); col += 1;
+                    while let Some(&d) = chars.peek() {
+                        if d.is_ascii_digit() {
+                            num_str.push(d);
+                            chars.next(); col += 1;
+                        } else { break; }
+                    }
+                    tokens.push(Token { kind: TokenKind::Float(num_str.parse().unwrap()), line, col: start_col, end_col: 0 });
+                } else if let Ok(n) = num_str.parse() {
+                    tokens.push(Token { kind: TokenKind::Number(n), line, col: start_col, end_col: 0 });
+                } else if let Ok(n) = num_str.parse() {
+                    tokens.push(Token { kind: TokenKind::WideNumber(n), line, col: start_col, end_col: 0 });
+                } else {
+                    diagnostics.push(Diagnostic::error(&format!("Number {} is too large", num_str)).at(line, start_col, col)
+                        .note(&format!("whole numbers go up to {}, seeds up to {}", i32::MAX, u64::MAX)));
+                    tokens.push(Token { kind: TokenKind::Number(0), line, col: start_col, end_col: 0 });
+                }
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 let start_col = col;
@@ -157,7 +205,9 @@ pub fn lexer(input: &str) -> Vec<Token> {
                     "FITNESS" => TokenKind::Fitness,
                     "VISUALIZE" => TokenKind::Visualize,
                     "ROUTINE" => TokenKind::Routine,
+                    "FUNCTION" => TokenKind::Function,
                     "SPAWN" => TokenKind::Spawn,
+                    "DIE" | "DESPAWN" => TokenKind::Die,
                     "AT" => TokenKind::At,
                     "RANDOM" => TokenKind::Random,
                     "IF" => TokenKind::If,
@@ -171,11 +221,19 @@ pub fn lexer(input: &str) -> Vec<Token> {
                     "FALSE" => TokenKind::False,
                     _ => TokenKind::Identifier(ident),
                 };
-                tokens.push(Token { kind, line, col: start_col });
+                tokens.push(Token { kind, line, col: start_col, end_col: 0 });
             }
-            _ => { chars.next(); col += 1; }
+            other => {
+                diagnostics.push(Diagnostic::warning(&format!("Unexpected character '{}' was ignored", other)).at(line, col, col + 1));
+                chars.next(); col += 1;
+            }
+        }
+        //the token ends where the lexer stopped reading it
+        if tokens.len() > before
+            && let Some(t) = tokens.last_mut() {
+            t.end_col = col;
         }
     }
-    tokens.push(Token { kind: TokenKind::EOF, line, col });
-    tokens
+    tokens.push(Token { kind: TokenKind::EOF, line, col, end_col: col + 1 });
+    (tokens, diagnostics)
 }
\ No newline at end of file
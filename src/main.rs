@@ -1,89 +1,318 @@
 
 // files tldr:
 // - types.rs    : data structures
+// - diagnostic.rs: errors and warnings with source positions, printed rustc-style
 // - lexer.rs    : converts text to tokens
 // - parser.rs   : converts tokens to syntax tree
 // - semantic.rs : checks for errors before running
 // - eval.rs     : runs the code
+// - bytecode.rs : compiles the syntax tree to bytecode (--vm)
+// - vm.rs       : runs the bytecode
+// - spatial.rs  : who stands on which cell (get_at, all_at)
 // - world.rs    : simulation logic
 // - evolution.rs: evolutionary alg logic
+// - simulation.rs: generation loop shared by gui and headless runs
+// - profiler.rs : time spent per routine and block (--profile)
+// - debugger.rs : breakpoints and stepping from a terminal prompt (--debug)
+// - render.rs   : VISUALIZE frames to PNG/GIF without a window (--render)
+// - reload.rs   : reloading the source file while the gui runs
+// - editor.rs   : source editor panel with highlighting and error underlines
+// - stats.rs    : per-generation statistics export (csv/json)
+// - checkpoint.rs: saving and resuming runs
 // - gui.rs      : visual display
 
 mod types;
+mod diagnostic;
 mod lexer;
 mod parser;
 mod eval;
+mod bytecode;
+mod vm;
+mod spatial;
 mod world;
 mod semantic;
 mod evolution;
+mod simulation;
+mod profiler;
+mod debugger;
+mod render;
+mod reload;
+mod editor;
+mod stats;
+mod checkpoint;
 mod gui;
 
+use std::process::ExitCode;
 use std::sync::Arc;
 use eframe::egui;
-use types::*;
-use lexer::lexer;
+use lexer::lex;
+use diagnostic::has_errors;
 use parser::Parser;
 use semantic::validate_program;
+use bytecode::compile;
+use simulation::Simulation;
+use stats::StatsWriter;
+use checkpoint::Checkpoint;
 use gui::SimApp;
+use render::RenderSpec;
 
-fn main() {
+const USAGE: &str = "Usage: simulanka <file.txt> [--headless] [--strict] [--vm] [--profile] [--seed N] [--stats-out file.csv|file.json]\n       [--checkpoint-every N] [--checkpoint-file file] [--resume file] [--debug] [--break LINE]\n       [--render gen=N[,out=file.gif|dir][,fps=F]]";
+
+//command line options
+#[derive(Debug, Default)]
+struct CliOptions {
+    path: String,
+    headless: bool, //run every generation without opening a window
+    strict: bool, //runtime errors stop the run instead of evaluating to 0
+    vm: bool, //run compiled bytecode instead of walking the syntax tree
+    profile: bool, //time routines and blocks, report at the end (or in the gui)
+    debug: bool, //step through one world at a terminal prompt
+    breakpoints: Vec<usize>, //lines the debugger stops at
+    seed: Option<u64>, //overrides the EVOLVE seed
+    stats_out: Option<String>, //where to write per-generation statistics
+    checkpoint_every: i32, //save a checkpoint every n generations
+    checkpoint_file: Option<String>, //defaults to <file.txt>.checkpoint
+    resume: Option<String>, //checkpoint to continue from
+    render: Option<RenderSpec>, //generation to export as PNG frames or a GIF (implies headless)
+}
+
+impl CliOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = CliOptions::default();
+        let mut path = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--strict" => options.strict = true,
+                "--vm" => options.vm = true,
+                "--profile" => options.profile = true,
+                "--debug" => options.debug = true,
+                "--break" => {
+                    let value = args.next().ok_or("--break needs a line number")?;
+                    let line = value.parse().map_err(|_| format!("Invalid line number: {}", value))?;
+                    options.breakpoints.push(line);
+                    options.debug = true;
+                }
+                "--seed" => {
+                    let value = args.next().ok_or("--seed needs a value")?;
+                    let seed = value.parse().map_err(|_| format!("Invalid seed: {}", value))?;
+                    options.seed = Some(seed);
+                }
+                "--stats-out" => {
+                    let value = args.next().ok_or("--stats-out needs a file")?;
+                    options.stats_out = Some(value.clone());
+                }
+                "--checkpoint-every" => {
+                    let value = args.next().ok_or("--checkpoint-every needs a value")?;
+                    let every = value.parse().ok().filter(|&n: &i32| n > 0)
+                        .ok_or(format!("Invalid checkpoint interval: {}", value))?;
+                    options.checkpoint_every = every;
+                }
+                "--checkpoint-file" => {
+                    let value = args.next().ok_or("--checkpoint-file needs a file")?;
+                    options.checkpoint_file = Some(value.clone());
+                }
+                "--resume" => {
+                    let value = args.next().ok_or("--resume needs a checkpoint file")?;
+                    options.resume = Some(value.clone());
+                }
+                "--render" => {
+                    let value = args.next().ok_or("--render needs gen=N")?;
+                    options.render = Some(RenderSpec::parse(value)?);
+                    options.headless = true;
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+                file => {
+                    if path.is_some() {
+                        return Err(format!("Unexpected argument: {}", file));
+                    }
+                    path = Some(file.to_string());
+                }
+            }
+        }
+        options.path = path.ok_or_else(|| "Missing source file".to_string())?;
+        if options.resume.is_some() && options.seed.is_some() {
+            return Err("--seed can't be combined with --resume (the checkpoint has its own seed)".to_string());
+        }
+        if options.debug && (options.vm || options.resume.is_some() || options.render.is_some()) {
+            return Err("--debug can't be combined with --vm, --resume or --render".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn main() -> ExitCode {
     // get command line arguments
     let args: Vec<String> = std::env::args().collect();
     
     // check usage
-    if args.len() < 2 {
-        println!("Usage: simulanka <file.txt>");
-        return;
-    }
+    let options = match CliOptions::parse(&args[1..]) {
+        Ok(o) => o,
+        Err(e) => { println!("{}\n{}", e, USAGE); return ExitCode::FAILURE; }
+    };
 
     // read and parse the source file
-    let input = match std::fs::read_to_string(&args[1]) {
+    let input = match std::fs::read_to_string(&options.path) {
         Ok(text) => text,
-        Err(e) => { println!("Error reading file: {}", e); return; }
+        Err(e) => { println!("Error reading file: {}", e); return ExitCode::FAILURE; }
     };
 
-    let tokens = lexer(&input);
+    let (tokens, mut diagnostics) = lex(&input);
     let mut parser = Parser::new(tokens);
-    
-    let program = match parser.parse_program() {
-        Ok(p) => Arc::new(p),
-        Err(e) => { println!("Parse Error: {}", e); return; }
+    let (mut program, parse_diagnostics) = parser.parse_program();
+    diagnostics.extend(parse_diagnostics);
+
+    // check for semantic errors. this runs even after syntax errors, on the part
+    // that parsed, so one run reports as much as possible
+    diagnostics.extend(validate_program(&program));
+    for d in &diagnostics {
+        println!("{}\n", d.render(&options.path, &input));
+    }
+    // warnings are printed but don't stop the run
+    if has_errors(&diagnostics) {
+        let count = diagnostics.iter().filter(|d| d.is_error()).count();
+        println!("aborting due to {} error(s)", count);
+        return ExitCode::FAILURE;
+    }
+
+    // a resumed run has to keep the seed it was started with
+    let resume = match &options.resume {
+        Some(path) => match Checkpoint::load(path) {
+            Ok(c) => Some(c),
+            Err(e) => { println!("Error: {}", e); return ExitCode::FAILURE; }
+        },
+        None => None,
     };
 
-    // check for semantic errors
-    if let Err(errors) = validate_program(&program) {
-        println!("Semantic Errors found:");
-        for e in errors { println!("  - {}", e); }
-        return;
+    // fix the run seed so it can be reported and replayed
+    let seed = match &resume {
+        Some(c) => c.seed,
+        None => options.seed.or(program.evolve_block.seed).unwrap_or_else(rand::random),
+    };
+    program.evolve_block.seed = Some(seed);
+    program.strict = options.strict;
+    program.profile = options.profile;
+    program.debug = options.debug;
+    if options.vm {
+        program.bytecode = Some(Arc::new(compile(&program)));
     }
+    println!("Seed: {}", seed);
+    let program = Arc::new(program);
 
-    // create world instances
-    let generations = program.evolve_block.generations;
-    let num_instances = program.evolve_block.instances;
-    
-    let mut instances = Vec::new();
-    for i in 0..num_instances {
-        let mut w = World::new(program.clone(), i);
-        w.spawn();
-        instances.push(w);
+    if options.debug {
+        return debugger::run(program, &input, options.breakpoints);
+    }
+
+    // open the stats file up front so a bad path fails before the run
+    let stats_out = match &options.stats_out {
+        Some(path) => match StatsWriter::open(path, &program, resume.as_ref().map(|c| c.generation)) {
+            Ok(w) => Some(w),
+            Err(e) => { println!("Error: {}", e); return ExitCode::FAILURE; }
+        },
+        None => None,
+    };
+
+    let mut sim = match &resume {
+        Some(_) => Simulation::without_worlds(program),
+        None => Simulation::new(program),
+    };
+    sim.stats_out = stats_out;
+    if options.checkpoint_every > 0 {
+        sim.checkpoint_every = options.checkpoint_every;
+        sim.checkpoint_path = Some(options.checkpoint_file.unwrap_or(format!("{}.checkpoint", options.path)));
     }
+    if let Some(c) = resume {
+        let generation = c.generation;
+        if let Err(e) = c.restore(&mut sim) {
+            println!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+        println!("Resuming after generation {}", generation);
+    }
+
+    // frames go next to the source file unless out= says otherwise
+    let render = options.render.map(|mut spec| {
+        if spec.out.is_none() {
+            spec.out = Some(format!("{}.gen{}", options.path, spec.generation));
+        }
+        spec
+    });
 
-    run_with_gui(instances, program, generations, num_instances);
+    if options.headless {
+        run_headless(sim, render)
+    } else {
+        run_with_gui(sim, &options.path, &input)
+    }
 }
 
-// run gui
-fn run_with_gui(instances: Vec<World>, program: Arc<Program>, generations: i32, num_instances: i32) {
+// run every generation from the EVOLVE block without a window
+fn run_headless(mut sim: Simulation, render: Option<RenderSpec>) -> ExitCode {
+    sim.record_steps = false;
+
+    if let Err(e) = sim.validate() {
+        println!("Error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    if let Some(spec) = &render
+        && (spec.generation <= sim.current_gen || spec.generation > sim.num_generations) {
+        println!("Error: can't render generation {}, the run covers generations {} to {}",
+            spec.generation, sim.current_gen + 1, sim.num_generations);
+        return ExitCode::FAILURE;
+    }
+
+    let start = std::time::Instant::now();
+    loop {
+        // only the rendered generation needs its steps recorded
+        sim.record_steps = render.as_ref().is_some_and(|spec| spec.generation == sim.current_gen + 1);
+        let report = match sim.run_generation() {
+            Some(report) => report,
+            None => break,
+        };
+        println!("{}", report);
+        if let (Some(spec), Some(snapshot)) = (&render, sim.history.last())
+            && spec.generation == report.generation {
+            let out = spec.out.clone().unwrap_or_default();
+            match render::export(&sim.program, snapshot, &out, spec.fps) {
+                Ok(message) => println!("{}", message),
+                Err(e) => { println!("Error: {}", e); return ExitCode::FAILURE; }
+            }
+        }
+    }
+    if !sim.profile.is_empty() {
+        println!("{}", sim.profile.report());
+    }
+    if let Some(e) = &sim.error {
+        println!("{}", e);
+        println!("Stopped in generation {}.", sim.current_gen);
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Finished {} generations. Global Best: {} (took {:?})",
+        sim.current_gen, sim.global_best_fitness, start.elapsed()
+    );
+    ExitCode::SUCCESS
+}
+
+// run gui, reloading the source file when it is saved
+fn run_with_gui(sim: Simulation, path: &str, source: &str) -> ExitCode {
+    let mut app = SimApp::new(sim);
+    app.watch(path, source);
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([800.0, 750.0])
+            .with_inner_size([1150.0, 750.0])
             .with_min_inner_size([700.0, 700.0]),
         ..Default::default()
     };
-    let _ = eframe::run_native(
+    let result = eframe::run_native(
         "Simulanka Evolution Simulator",
         options,
         Box::new(move |_| {
-            Ok(Box::new(SimApp::new(instances, program, generations, num_instances)))
+            Ok(Box::new(app))
         }),
     );
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => { println!("GUI Error: {}", e); ExitCode::FAILURE }
+    }
 }
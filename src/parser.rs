@@ -1,6 +1,6 @@
 use crate::lexer::{Token, TokenKind};
 use crate::types::*;
-use std::collections::HashMap;
+use crate::diagnostic::Diagnostic;
 // parses tokens
 //also checks that all blocks are present 
 // blocks environment, evolve dont have code just parameters
@@ -10,16 +10,27 @@ pub struct EnvDef {
     pub width: i32,
     pub height: i32,
     pub steps: i32,
+    pub occupancy: Occupancy,
+    pub update: UpdateMode,
+    pub activation: Activation,
+    pub coordinates: Coordinates,
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    context: String, //block being parsed, reported with errors
+    diagnostics: Vec<Diagnostic>, //errors parsing went on after
+}
+
+//the columns a token covers
+fn token_span(t: &Token) -> Span {
+    Span { line: t.line, col: t.col, end_col: t.end_col }
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+        Self { tokens, pos: 0, context: String::new(), diagnostics: Vec::new() }
     }
 
     fn peek(&self) -> &Token {
@@ -32,12 +43,89 @@ impl Parser {
         t
     }
 
-    fn error(&self, msg: &str) -> String {
-        let t = self.peek();
-        format!("Error at line {}:{}: {}", t.line, t.col, msg)
+    //error at the next token
+    fn error(&self, msg: &str) -> Diagnostic {
+        self.error_at(self.peek(), msg)
+    }
+
+    fn error_at(&self, t: &Token, msg: &str) -> Diagnostic {
+        let d = Diagnostic::error(msg).at(t.line, t.col, t.end_col);
+        if self.context.is_empty() { d } else { d.context(&self.context) }
     }
 
-    fn expect(&mut self, expected: TokenKind) -> Result<(), String> {
+    //span of the token that was just consumed
+    fn previous_span(&self) -> Span {
+        token_span(&self.tokens[self.pos.saturating_sub(1)])
+    }
+
+    //the name of something, `what` is the error if it isn't there
+    fn expect_identifier(&mut self, what: &str) -> Result<String, Diagnostic> {
+        if let TokenKind::Identifier(n) = &self.peek().kind {
+            let n = n.clone();
+            self.advance();
+            Ok(n)
+        } else {
+            Err(self.error(what))
+        }
+    }
+
+    //parsing goes on after an error, so the same spot can fail twice
+    //(a broken command and then the block it's in). keep only the first
+    fn report(&mut self, d: Diagnostic) {
+        let repeated = self.diagnostics.iter().any(|e| e.line == d.line && e.col == d.col && e.message == d.message);
+        if !repeated {
+            self.diagnostics.push(d);
+        }
+    }
+
+    //a token parsing can pick up again at after an error: a top level block
+    //or a ROUTINE inside SPECIES
+    fn at_block_start(&self) -> bool {
+        let next = self.tokens.get(self.pos + 1).map(|t| &t.kind);
+        match self.peek().kind {
+            TokenKind::Function => true,
+            //not the `routine: name` property of a species
+            TokenKind::Routine => matches!(next, Some(TokenKind::Identifier(_))),
+            //not `for a in environment { ... }`
+            TokenKind::Environment => {
+                next == Some(&TokenKind::LBrace) && (self.pos == 0 || self.tokens[self.pos - 1].kind != TokenKind::In)
+            }
+            //SPAWN { ... } but not the spawn command
+            TokenKind::Species | TokenKind::Evolve | TokenKind::Fitness | TokenKind::Mutate
+            | TokenKind::Visualize | TokenKind::Spawn => next == Some(&TokenKind::LBrace),
+            _ => false,
+        }
+    }
+
+    //skip the rest of a broken command: past the next `;` or a `{ ... }` it
+    //opened, or up to the `}` closing the block or the start of the next block
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.peek().kind {
+                TokenKind::EOF => return,
+                TokenKind::SemiColon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::RBrace if depth == 0 => return,
+                TokenKind::RBrace => {
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                    continue;
+                }
+                TokenKind::LBrace => depth += 1,
+                _ if self.at_block_start() => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn expect(&mut self, expected: TokenKind) -> Result<(), Diagnostic> {
         if self.peek().kind == expected {
             self.advance();
             Ok(())
@@ -46,83 +134,101 @@ impl Parser {
         }
     }
 
-    //the entry point- parses the whole file into the program struct
-    pub fn parse_program(&mut self) -> Result<Program, String> {
+    //the entry point- parses the whole file into the program struct.
+    //errors don't stop it: a broken command is skipped up to the next `;` or
+    //`}`, a broken block up to the next block keyword. the program is as much
+    //as could be parsed, so semantic checks can run on it too
+    pub fn parse_program(&mut self) -> (Program, Vec<Diagnostic>) {
         let mut program = Program::default();
-        let mut found_environment = false;
-        let mut found_species = false;
-        let mut found_evolve = false;
-        let mut found_fitness = false;
-        let mut found_mutate = false;
-        let mut found_spawn = false;
+        let mut found = Vec::new();
 
         while self.peek().kind != TokenKind::EOF {
-            match self.peek().kind {
-                TokenKind::Environment => {
-                    let env = self.parse_env_block()?;
-                    program.env_width = env.width;
-                    program.env_height = env.height;
-                    program.env_steps = env.steps;
-                    found_environment = true;
-                }
-                TokenKind::Species => {
-                    self.parse_species_block(&mut program)?;
-                    found_species = true;
-                }
-                TokenKind::Evolve => {
-                    self.parse_evolve_block(&mut program)?;
-                    found_evolve = true;
-                }
-                TokenKind::Fitness => {
-                    program.fitness_block = self.parse_fitness_block()?;
-                    found_fitness = true;
-                }
-                TokenKind::Mutate => {
-                    program.mutations_block = self.parse_mutate_block()?;
-                    found_mutate = true;
-                }
-                TokenKind::Visualize => {
-                    self.advance(); //bc it uses basic parsing instead of specialized
-                    program.visualize_block = self.parse_block()?;
-                    program.visualize = true;
-                }
-                TokenKind::Spawn => {
-                    program.spawns_block = self.parse_spawn_block()?;
-                    found_spawn = true;
-                }
-                _ => { 
+            if let Err(e) = self.parse_top_level(&mut program, &mut found) {
+                self.report(e);
+                while self.peek().kind != TokenKind::EOF && !self.at_block_start() {
                     self.advance();
                 }
             }
         }
 
-        if !found_environment {
-            return Err("Syntax Error: Missing obligatory ENVIRONMENT block".to_string());
-        }
-        if !found_species {
-            return Err("Syntax Error: Missing obligatory SPECIES block".to_string());
-        }
-        if !found_evolve {
-             return Err("Syntax Error: Missing obligatory EVOLVE block".to_string());
-        }
-        if !found_fitness {
-            return Err("Syntax Error: Missing obligatory FITNESS block".to_string());
-        }
-        if !found_mutate {
-             return Err("Syntax Error: Missing obligatory MUTATE block".to_string());
-        }
-        if !found_spawn {
-             return Err("Syntax Error: Missing obligatory SPAWN block".to_string());
+        for block in ["ENVIRONMENT", "SPECIES", "EVOLVE", "FITNESS", "MUTATE", "SPAWN"] {
+            if !found.contains(&block) {
+                self.diagnostics.push(Diagnostic::error(&format!("Missing obligatory {} block", block))
+                    .note("every program needs ENVIRONMENT, SPECIES, SPAWN, FITNESS, MUTATE and EVOLVE blocks"));
+            }
         }
 
-        Ok(program)
+        (program, std::mem::take(&mut self.diagnostics))
+    }
+
+    //one block, or a stray token that is skipped. a block counts as found
+    //even if it is broken, so it isn't also reported missing
+    fn parse_top_level(&mut self, program: &mut Program, found: &mut Vec<&'static str>) -> Result<(), Diagnostic> {
+        match self.peek().kind {
+            TokenKind::Environment => {
+                self.context = "ENVIRONMENT".to_string();
+                found.push("ENVIRONMENT");
+                let env = self.parse_env_block()?;
+                program.env_width = env.width;
+                program.env_height = env.height;
+                program.env_steps = env.steps;
+                program.env_occupancy = env.occupancy;
+                program.env_update = env.update;
+                program.env_activation = env.activation;
+                program.env_coordinates = env.coordinates;
+            }
+            TokenKind::Species => {
+                self.context = "SPECIES".to_string();
+                found.push("SPECIES");
+                self.parse_species_block(program)?;
+            }
+            TokenKind::Evolve => {
+                self.context = "EVOLVE".to_string();
+                found.push("EVOLVE");
+                self.parse_evolve_block(program)?;
+            }
+            TokenKind::Fitness => {
+                self.context = "FITNESS".to_string();
+                found.push("FITNESS");
+                program.fitness_block = self.parse_fitness_block()?;
+            }
+            TokenKind::Mutate => {
+                self.context = "MUTATE".to_string();
+                found.push("MUTATE");
+                program.mutations_block = self.parse_mutate_block()?;
+            }
+            TokenKind::Visualize => {
+                self.context = "VISUALIZE".to_string();
+                self.advance(); //bc it uses basic parsing instead of specialized
+                program.visualize = true;
+                program.visualize_block = self.parse_block()?;
+            }
+            TokenKind::Spawn => {
+                self.context = "SPAWN".to_string();
+                found.push("SPAWN");
+                program.spawns_block = self.parse_spawn_block()?;
+            }
+            TokenKind::Function => {
+                self.parse_function_def(program)?;
+            }
+            //only reached after an error in SPECIES skipped to one of its routines
+            TokenKind::Routine if self.at_block_start() => {
+                self.context = "SPECIES".to_string();
+                let routine = self.parse_routine_def()?;
+                program.routines_block.insert(routine.name.clone(), routine);
+            }
+            _ => {
+                self.advance();
+            }
+        }
+        Ok(())
     }
 
     //specific parsers for each block
-    fn parse_env_block(&mut self) -> Result<EnvDef, String> {
+    fn parse_env_block(&mut self) -> Result<EnvDef, Diagnostic> {
         self.expect(TokenKind::Environment)?;
         self.expect(TokenKind::LBrace)?;
-        let mut env = EnvDef { width: 50, height: 50, steps: 10 };
+        let mut env = EnvDef { width: 50, height: 50, steps: 10, occupancy: Occupancy::Stack, update: UpdateMode::Sequential, activation: Activation::Spawn, coordinates: Coordinates::Pixels };
         while self.peek().kind != TokenKind::RBrace {
             let key = match self.peek().kind {
                 TokenKind::Identifier(ref n) => n.clone(),
@@ -134,6 +240,10 @@ impl Parser {
                 "width" => if let TokenKind::Number(v) = self.advance().kind { env.width = v; },
                 "height" => if let TokenKind::Number(v) = self.advance().kind { env.height = v; },
                 "steps" => if let TokenKind::Number(v) = self.advance().kind { env.steps = v; },
+                "occupancy" => env.occupancy = self.parse_occupancy()?,
+                "update" => env.update = self.parse_update_mode()?,
+                "activation" => env.activation = self.parse_activation()?,
+                "coordinates" => env.coordinates = self.parse_coordinates()?,
                 _ => { self.advance(); }
             }
             if self.peek().kind == TokenKind::Comma { self.advance(); }
@@ -142,7 +252,7 @@ impl Parser {
         Ok(env)
     }
 
-    fn parse_species_block(&mut self, program: &mut Program) -> Result<(), String> {
+    fn parse_species_block(&mut self, program: &mut Program) -> Result<(), Diagnostic> {
         self.expect(TokenKind::Species)?;
         self.expect(TokenKind::LBrace)?;
 
@@ -153,19 +263,25 @@ impl Parser {
                 if self.peek().kind == TokenKind::Comma { self.advance(); }
                 continue;
             }
+            if self.peek().kind == TokenKind::Function {
+                self.parse_function_def(program)?;
+                if self.peek().kind == TokenKind::Comma { self.advance(); }
+                continue;
+            }
 
-            let name = if let TokenKind::Identifier(n) = self.advance().kind { n } else { return Err(self.error("Expected species name")); };
+            let name = self.expect_identifier("Expected species name")?;
             
             self.expect(TokenKind::LBrace)?;
-            let mut props = HashMap::new();
+            let mut props: Vec<(String, Exp)> = Vec::new();
             let mut routine_call = String::new();
             
             while self.peek().kind != TokenKind::RBrace {
-                let prop_key = match self.advance().kind {
-                    TokenKind::Identifier(n) => n,
+                let prop_key = match &self.peek().kind {
+                    TokenKind::Identifier(n) => n.clone(),
                     TokenKind::Routine => "routine".into(),
-                    _ => return Err(self.error("Property Key")),
+                    _ => return Err(self.error("Expected property name")),
                 };
+                self.advance();
                 self.expect(TokenKind::Colon)?;
                 let val = self.parse_exp()?;
                 if self.peek().kind == TokenKind::SemiColon { self.advance(); }
@@ -174,7 +290,11 @@ impl Parser {
                 if prop_key == "routine" { 
                     if let Exp::Var(v, _) = val { routine_call = v; }
                 } 
-                else { props.insert(prop_key, val); }
+                else if let Some(prop) = props.iter_mut().find(|(k, _)| *k == prop_key) {
+                    //a repeated property keeps its first place, the last value wins
+                    prop.1 = val;
+                }
+                else { props.push((prop_key, val)); }
             }
             self.expect(TokenKind::RBrace)?;
             program.species_block.insert(name.clone(), SpeciesDef { properties: props, routine_call });
@@ -184,23 +304,23 @@ impl Parser {
         Ok(())
     }
 
-    fn parse_spawn_block(&mut self) -> Result<Vec<Command>, String> {
+    fn parse_spawn_block(&mut self) -> Result<Vec<Command>, Diagnostic> {
         self.expect(TokenKind::Spawn)?;
         self.parse_block()
     }
 
-    fn parse_fitness_block(&mut self) -> Result<FitnessBlock, String> {
+    fn parse_fitness_block(&mut self) -> Result<FitnessBlock, Diagnostic> {
         self.expect(TokenKind::Fitness)?;
         let commands = self.parse_block()?;
         Ok(FitnessBlock { commands })
     }
 
-    fn parse_mutate_block(&mut self) -> Result<Vec<MutationRule>, String> {
+    fn parse_mutate_block(&mut self) -> Result<Vec<MutationRule>, Diagnostic> {
         self.expect(TokenKind::Mutate)?;
         self.expect(TokenKind::LBrace)?;
         let mut rules = Vec::new();
         while self.peek().kind != TokenKind::RBrace {
-            let key = if let TokenKind::Identifier(n) = self.advance().kind { n } else { return Err(self.error("Expected key")); };
+            let key = self.expect_identifier("Expected key")?;
             self.expect(TokenKind::Colon)?;
             
             let body = self.parse_block()?;
@@ -211,15 +331,19 @@ impl Parser {
         Ok(rules)
     }
 
-    fn parse_evolve_block(&mut self, program: &mut Program) -> Result<(), String> {
+    fn parse_evolve_block(&mut self, program: &mut Program) -> Result<(), Diagnostic> {
         self.expect(TokenKind::Evolve)?;
         self.expect(TokenKind::LBrace)?;
         while self.peek().kind != TokenKind::RBrace {
-            let key = if let TokenKind::Identifier(n) = self.advance().kind { n } else { return Err(self.error("Expected key")); };
+            let key = self.expect_identifier("Expected key")?;
             self.expect(TokenKind::Colon)?;
             match key.as_str() {
                 "generations" => if let TokenKind::Number(n) = self.advance().kind { program.evolve_block.generations = n; },
                 "instances" => if let TokenKind::Number(n) = self.advance().kind { program.evolve_block.instances = n; },
+                "seed" => program.evolve_block.seed = Some(self.parse_seed()?),
+                "selection" => program.evolve_block.selection = self.parse_selection()?,
+                "elitism" => if let TokenKind::Number(n) = self.advance().kind { program.evolve_block.elitism = n; },
+                "survivors" => if let TokenKind::Number(n) = self.advance().kind { program.evolve_block.survivors = Some(n); },
                 _ => { self.advance(); }
             }
             if self.peek().kind == TokenKind::Comma { self.advance(); }
@@ -228,25 +352,150 @@ impl Parser {
         Ok(())
     }
 
-    fn parse_routine_def(&mut self) -> Result<RoutineDef, String> {
+    //seed: any whole number a run prints as its seed, 0 to u64::MAX
+    fn parse_seed(&mut self) -> Result<u64, Diagnostic> {
+        let t = self.advance();
+        match t.kind {
+            TokenKind::Number(n) if n >= 0 => Ok(n as u64),
+            TokenKind::WideNumber(n) => Ok(n),
+            _ => Err(self.error_at(&t, &format!("Expected a seed from 0 to {}", u64::MAX))),
+        }
+    }
+
+    //occupancy: stack | block
+    fn parse_occupancy(&mut self) -> Result<Occupancy, Diagnostic> {
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier("Expected occupancy policy")?;
+        match name.as_str() {
+            "stack" => Ok(Occupancy::Stack),
+            "block" => Ok(Occupancy::Block),
+            _ => Err(self.error_at(&name_token, &format!("Unknown occupancy policy '{}', expected stack or block", name))),
+        }
+    }
+
+    //update: sequential | synchronous | random_order
+    fn parse_update_mode(&mut self) -> Result<UpdateMode, Diagnostic> {
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier("Expected update mode")?;
+        match name.as_str() {
+            "sequential" => Ok(UpdateMode::Sequential),
+            "synchronous" => Ok(UpdateMode::Synchronous),
+            "random_order" => Ok(UpdateMode::RandomOrder),
+            _ => Err(self.error_at(&name_token, &format!("Unknown update mode '{}', expected sequential, synchronous or random_order", name))),
+        }
+    }
+
+    //activation: spawn | shuffled | priority(property)
+    fn parse_activation(&mut self) -> Result<Activation, Diagnostic> {
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier("Expected activation order")?;
+        match name.as_str() {
+            "spawn" => Ok(Activation::Spawn),
+            "shuffled" => Ok(Activation::Shuffled),
+            "priority" => {
+                self.expect(TokenKind::LParen)?;
+                let property = self.expect_identifier("Expected property name")?;
+                self.expect(TokenKind::RParen)?;
+                Ok(Activation::Priority(property))
+            }
+            _ => Err(self.error_at(&name_token, &format!("Unknown activation order '{}', expected spawn, shuffled or priority(property)", name))),
+        }
+    }
+
+    //coordinates: pixels | cells
+    fn parse_coordinates(&mut self) -> Result<Coordinates, Diagnostic> {
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier("Expected coordinates")?;
+        match name.as_str() {
+            "pixels" => Ok(Coordinates::Pixels),
+            "cells" => Ok(Coordinates::Cells),
+            _ => Err(self.error_at(&name_token, &format!("Unknown coordinates '{}', expected pixels or cells", name))),
+        }
+    }
+
+    //selection: truncation | tournament(size) | roulette | rank
+    fn parse_selection(&mut self) -> Result<Selection, Diagnostic> {
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier("Expected selection strategy")?;
+        match name.as_str() {
+            "truncation" => Ok(Selection::Truncation),
+            "roulette" => Ok(Selection::Roulette),
+            "rank" => Ok(Selection::Rank),
+            "tournament" => {
+                self.expect(TokenKind::LParen)?;
+                let size_token = self.peek().clone();
+                let size = if let TokenKind::Number(n) = self.advance().kind { n } else { return Err(self.error_at(&size_token, "Expected tournament size")); };
+                self.expect(TokenKind::RParen)?;
+                if size < 1 {
+                    return Err(self.error_at(&size_token, "Tournament size must be at least 1"));
+                }
+                Ok(Selection::Tournament(size as usize))
+            }
+            _ => Err(self.error_at(&name_token, &format!("Unknown selection strategy '{}'", name))),
+        }
+    }
+
+    fn parse_routine_def(&mut self) -> Result<RoutineDef, Diagnostic> {
         self.expect(TokenKind::Routine)?;
-        let name = if let TokenKind::Identifier(n) = self.advance().kind { n } else { return Err(self.error("Name")); };
-        let body = self.parse_block()?;
-        Ok(RoutineDef { name, body })
+        let name = self.expect_identifier("Expected routine name")?;
+        let outer = std::mem::replace(&mut self.context, format!("ROUTINE {}", name));
+        let body = self.parse_block();
+        self.context = outer;
+        Ok(RoutineDef { name, body: body? })
+    }
+
+    //FUNCTION name(a, b) { ... return ...; }
+    fn parse_function_def(&mut self, program: &mut Program) -> Result<(), Diagnostic> {
+        let line = self.peek().line;
+        self.expect(TokenKind::Function)?;
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier("Expected function name")?;
+        let outer = std::mem::replace(&mut self.context, format!("FUNCTION {}", name));
+        self.expect(TokenKind::LParen)?;
+        let mut params = Vec::new();
+        while self.peek().kind != TokenKind::RParen {
+            let param_token = self.peek().clone();
+            let param = self.expect_identifier("Expected parameter name")?;
+            if params.contains(&param) {
+                self.report(self.error_at(&param_token, &format!("Duplicate parameter '{}' in FUNCTION {}", param, name)));
+            } else {
+                params.push(param);
+            }
+            if self.peek().kind == TokenKind::Comma { self.advance(); }
+        }
+        let body = match self.expect(TokenKind::RParen) {
+            Ok(()) => self.parse_block(),
+            Err(e) => Err(e),
+        };
+        self.context = outer;
+        let body = body?;
+        if program.functions_block.contains_key(&name) {
+            self.report(self.error_at(&name_token, &format!("FUNCTION {} is defined twice", name)));
+            return Ok(());
+        }
+        program.functions_block.insert(name.clone(), FunctionDef { name, params, body, line });
+        Ok(())
     }
 
     //non specific parsers
-    pub fn parse_block(&mut self) -> Result<Vec<Command>, String> {
+    pub fn parse_block(&mut self) -> Result<Vec<Command>, Diagnostic> {
         self.expect(TokenKind::LBrace)?;
         let mut cmds = Vec::new();
-        while self.peek().kind != TokenKind::RBrace {
-            cmds.push(self.parse_command()?);
+        //a broken command is reported and skipped, the rest of the block still parses
+        while self.peek().kind != TokenKind::RBrace && self.peek().kind != TokenKind::EOF && !self.at_block_start() {
+            match self.parse_command() {
+                Ok(cmd) => cmds.push(cmd),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize();
+                }
+            }
         }
         self.expect(TokenKind::RBrace)?;
         Ok(cmds)
     }
 
-    pub fn parse_command(&mut self) -> Result<Command, String> {
+    pub fn parse_command(&mut self) -> Result<Command, Diagnostic> {
         let line = self.peek().line;
         match self.peek().kind {
             TokenKind::If => {
@@ -276,13 +525,14 @@ impl Parser {
             }
             TokenKind::For => {
                 self.advance();
-                let var = if let TokenKind::Identifier(n) = self.advance().kind { n } else { return Err(self.error("Expected var")); };
+                let var = self.expect_identifier("Expected var")?;
                 self.expect(TokenKind::In)?;
-                let collection = match self.advance().kind {
-                    TokenKind::Identifier(n) => n,
+                let collection = match &self.peek().kind {
+                    TokenKind::Identifier(n) => n.clone(),
                     TokenKind::Environment => "environment".to_string(),
                     _ => return Err(self.error("Expected collection")),
                 };
+                self.advance();
                 let body = self.parse_block()?;
                 Ok(Command::For { var, collection, body, line })
             }
@@ -306,7 +556,7 @@ impl Parser {
             }
             TokenKind::Spawn => {
                 self.advance();
-                let species = if let TokenKind::Identifier(n) = self.advance().kind { n } else { return Err(self.error("Expected species")); };
+                let species = self.expect_identifier("Expected species")?;
                 self.expect(TokenKind::At)?;
                 self.expect(TokenKind::LParen)?;
                 let x = self.parse_exp()?;
@@ -316,6 +566,11 @@ impl Parser {
                 if self.peek().kind == TokenKind::SemiColon { self.advance(); }
                 Ok(Command::Spawn { species, x, y, line })
             }
+            TokenKind::Die => {
+                self.advance();
+                if self.peek().kind == TokenKind::SemiColon { self.advance(); }
+                Ok(Command::Die(line))
+            }
             _ => {
                 let exp = self.parse_exp()?;
                 if self.peek().kind == TokenKind::Equal {
@@ -331,11 +586,11 @@ impl Parser {
         }
     }
 
-    pub fn parse_exp(&mut self) -> Result<Exp, String> {
+    pub fn parse_exp(&mut self) -> Result<Exp, Diagnostic> {
         self.parse_sum()
     }
 
-    fn parse_sum(&mut self) -> Result<Exp, String> {
+    fn parse_sum(&mut self) -> Result<Exp, Diagnostic> {
         let mut left = self.parse_term()?;
         while matches!(self.peek().kind, TokenKind::Plus | TokenKind::Minus) {
             let tok = self.advance();
@@ -345,13 +600,13 @@ impl Parser {
                 _ => unreachable!(),
             };
             let right = self.parse_term()?;
-            let line = tok.line;
-            left = Exp::BinaryOp(Box::new(left), op, Box::new(right), line);
+            let span = left.span().to(right.span());
+            left = Exp::BinaryOp(Box::new(left), op, Box::new(right), span);
         }
         Ok(left)
     }
 
-    fn parse_term(&mut self) -> Result<Exp, String> {
+    fn parse_term(&mut self) -> Result<Exp, Diagnostic> {
         let mut left = self.parse_primary()?;
         while matches!(self.peek().kind, TokenKind::Star | TokenKind::Slash | TokenKind::Percent) {
             let tok = self.advance();
@@ -362,8 +617,8 @@ impl Parser {
                 _ => unreachable!(),
             };
             let right = self.parse_primary()?;
-            let line = tok.line;
-            left = Exp::BinaryOp(Box::new(left), op, Box::new(right), line);
+            let span = left.span().to(right.span());
+            left = Exp::BinaryOp(Box::new(left), op, Box::new(right), span);
         }
         Ok(left)
     }
@@ -380,24 +635,29 @@ impl Parser {
         }
     }
 
-    fn parse_primary(&mut self) -> Result<Exp, String> {
+    fn parse_primary(&mut self) -> Result<Exp, Diagnostic> {
         // handle negative numbers: -5 becomes (0 - 5)
         if self.peek().kind == TokenKind::Minus {
             let tok = self.advance();
             let right = self.parse_primary()?;
-            let line = tok.line;
-            let mut node = Exp::BinaryOp(Box::new(Exp::Int(0, line)), "-".into(), Box::new(right), line);
+            let span = token_span(&tok).to(right.span());
+            let mut node = Exp::BinaryOp(Box::new(Exp::Int(0, token_span(&tok))), "-".into(), Box::new(right), span);
             node = self.parse_dot_and_index(node)?;
             return Ok(node);
         }
 
         let t = self.advance();
-        let line = t.line;
+        let t_start = t.clone();
+        let span = token_span(&t);
         let mut node = match t.kind {
-            TokenKind::Number(v) => Exp::Int(v, line),
-            TokenKind::StringLiteral(s) => Exp::StringLiteral(s, line),
-            TokenKind::True => Exp::Bool(true, line),
-            TokenKind::False => Exp::Bool(false, line),
+            TokenKind::Number(v) => Exp::Int(v, span),
+            TokenKind::Float(v) => Exp::Float(v, span),
+            TokenKind::WideNumber(_) => {
+                return Err(self.error_at(&t_start, &format!("Number is too large, whole numbers go up to {}", i32::MAX)));
+            }
+            TokenKind::StringLiteral(s) => Exp::StringLiteral(s, span),
+            TokenKind::True => Exp::Bool(true, span),
+            TokenKind::False => Exp::Bool(false, span),
             TokenKind::LBracket => {
                 let mut exps = Vec::new();
                 while self.peek().kind != TokenKind::RBracket {
@@ -405,7 +665,7 @@ impl Parser {
                     if self.peek().kind == TokenKind::Comma { self.advance(); }
                 }
                 self.expect(TokenKind::RBracket)?;
-                Exp::List(exps, line)
+                Exp::List(exps, span.to(self.previous_span()))
             }
             TokenKind::LParen => {
                 let exp = self.parse_exp()?;
@@ -427,13 +687,15 @@ impl Parser {
                         if self.peek().kind == TokenKind::Comma { self.advance(); }
                     }
                     self.expect(TokenKind::RParen)?;
-                    Exp::Call(name, args, line)
+                    Exp::Call(name, args, span.to(self.previous_span()))
                 } else {
-                    Exp::Var(name, line)
+                    Exp::Var(name, span)
                 }
             },
-            _ => {
-                return Err(self.error(&format!("Expected exp, found {:?}", t.kind)));
+            other => {
+                //put it back, it may be the `;` or `}` error recovery syncs at
+                if other != TokenKind::EOF { self.pos -= 1; }
+                return Err(self.error_at(&t_start, &format!("Expected exp, found {:?}", other)));
             }
         };
         
@@ -442,25 +704,26 @@ impl Parser {
     }
 
     // parse .field and [index] access after an expression
-    fn parse_dot_and_index(&mut self, mut node: Exp) -> Result<Exp, String> {
+    fn parse_dot_and_index(&mut self, mut node: Exp) -> Result<Exp, Diagnostic> {
         while matches!(self.peek().kind, TokenKind::Dot | TokenKind::LBracket) {
             let tok = self.advance();
-            let line = tok.line;
             if tok.kind == TokenKind::Dot {
                 let field_token = self.advance();
                 let field_name = self.token_to_field_name(&field_token)
-                    .ok_or_else(|| self.error(&format!("Expected field name after '.', found {:?}", field_token.kind)))?;
-                node = Exp::Dot(Box::new(node), field_name, line);
+                    .ok_or_else(|| self.error_at(&field_token, &format!("Expected field name after '.', found {:?}", field_token.kind)))?;
+                let span = node.span().to(token_span(&field_token));
+                node = Exp::Dot(Box::new(node), field_name, span);
             } else {
                 let idx = self.parse_exp()?;
                 self.expect(TokenKind::RBracket)?;
-                node = Exp::Index(Box::new(node), Box::new(idx), line);
+                let span = node.span().to(self.previous_span());
+                node = Exp::Index(Box::new(node), Box::new(idx), span);
             }
         }
         Ok(node)
     }
 
-    pub fn parse_bexp(&mut self) -> Result<BExp, String> {
+    pub fn parse_bexp(&mut self) -> Result<BExp, Diagnostic> {
         let mut left = self.parse_and_exp()?;
         while self.peek().kind == TokenKind::Or {
             self.advance();
@@ -470,7 +733,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_and_exp(&mut self) -> Result<BExp, String> {
+    fn parse_and_exp(&mut self) -> Result<BExp, Diagnostic> {
         let mut left = self.parse_primary_bexp()?;
         while self.peek().kind == TokenKind::And {
             self.advance();
@@ -480,9 +743,10 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_primary_bexp(&mut self) -> Result<BExp, String> {
+    fn parse_primary_bexp(&mut self) -> Result<BExp, Diagnostic> {
         let left = self.parse_exp()?;
-        let op = self.advance().kind;
+        let op_token = self.advance();
+        let op = op_token.kind.clone();
         let right = self.parse_exp()?;
         match op {
             TokenKind::Greater => Ok(BExp::Greater(left, right)),
@@ -491,7 +755,7 @@ impl Parser {
             TokenKind::LessEqual => Ok(BExp::LessEqual(left, right)),
             TokenKind::DoubleEqual => Ok(BExp::Equal(left, right)),
             TokenKind::NotEqual => Ok(BExp::NotEqual(left, right)),
-            _ => Err(self.error(&format!("Expected comparison operator, found {:?}", op))),
+            _ => Err(self.error_at(&op_token, &format!("Expected comparison operator, found {:?}", op))),
         }
     }
 }
\ No newline at end of file
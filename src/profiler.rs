@@ -0,0 +1,97 @@
+//profiler.rs - where the time of a generation goes (--profile)
+//every world records its own timings while it runs, so the rayon workers
+//never share anything. the simulation merges them after each parallel phase.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing {
+    pub calls: u64,
+    pub total: Duration,
+}
+
+impl Timing {
+    pub fn per_call(&self) -> Duration {
+        Duration::from_secs_f64(self.total.as_secs_f64() / self.calls.max(1) as f64)
+    }
+}
+
+//time and call count per section: "ROUTINE move", "FITNESS", "MUTATE crossover", ...
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    sections: HashMap<String, Timing>,
+}
+
+impl Profile {
+    //add one call that started at `start` (None when profiling is off)
+    pub fn record(&mut self, section: &str, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.add(section, start.elapsed());
+        }
+    }
+
+    pub fn add(&mut self, section: &str, elapsed: Duration) {
+        match self.sections.get_mut(section) {
+            Some(timing) => {
+                timing.calls += 1;
+                timing.total += elapsed;
+            }
+            None => {
+                self.sections.insert(section.to_string(), Timing { calls: 1, total: elapsed });
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: Profile) {
+        for (section, timing) in other.sections {
+            let entry = self.sections.entry(section).or_default();
+            entry.calls += timing.calls;
+            entry.total += timing.total;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.sections.clear();
+    }
+
+    //slowest section first
+    pub fn rows(&self) -> Vec<(String, Timing)> {
+        let mut rows: Vec<(String, Timing)> = self.sections.iter().map(|(s, t)| (s.clone(), *t)).collect();
+        rows.sort_by(|a, b| b.1.total.cmp(&a.1.total).then_with(|| a.0.cmp(&b.0)));
+        rows
+    }
+
+    //share of all recorded time, in percent
+    pub fn share(&self, timing: &Timing) -> f64 {
+        let mut all = Duration::ZERO;
+        for t in self.sections.values() {
+            all += t.total;
+        }
+        if all.is_zero() { 0.0 } else { timing.total.as_secs_f64() * 100.0 / all.as_secs_f64() }
+    }
+
+    //table for the end of a headless run. times are summed over all instances,
+    //so with several rayon workers they add up to more than the wall clock time
+    pub fn report(&self) -> String {
+        let mut lines = vec![format!(
+            "{:<24} {:>12} {:>7} {:>10} {:>12}",
+            "Profile", "total", "share", "calls", "per call"
+        )];
+        for (section, timing) in self.rows() {
+            lines.push(format!(
+                "{:<24} {:>12} {:>6.1}% {:>10} {:>12}",
+                section,
+                format!("{:.2?}", timing.total),
+                self.share(&timing),
+                timing.calls,
+                format!("{:.2?}", timing.per_call()),
+            ));
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,115 @@
+//reload.rs - hot reload of the source file in the gui
+//the window polls the file's modification time. a changed file goes through
+//the same lexer -> parser -> semantic pipeline as at startup, and the gui
+//decides whether the new program can be swapped in or needs a restart.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::types::*;
+use crate::lexer::{lex, lexer, TokenKind};
+use crate::parser::Parser;
+use crate::semantic::validate_program;
+use crate::bytecode::compile;
+use crate::diagnostic::{has_errors, Diagnostic};
+
+//how often the file is checked
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct SourceWatcher {
+    pub path: String,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl SourceWatcher {
+    pub fn new(path: &str) -> Self {
+        SourceWatcher {
+            path: path.to_string(),
+            modified: modified_time(path),
+            last_poll: Instant::now(),
+        }
+    }
+
+    //new text of the file if it was saved since the last poll
+    pub fn poll(&mut self) -> Option<String> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        std::fs::read_to_string(&self.path).ok()
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+//lexer -> parser -> semantic checks, the same steps as at startup.
+//a program that passes comes with its warnings, one that fails with every
+//diagnostic found
+pub fn check_source(source: &str) -> Result<(Program, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let (tokens, mut diagnostics) = lex(source);
+    let mut parser = Parser::new(tokens);
+    let (program, parse_diagnostics) = parser.parse_program();
+    diagnostics.extend(parse_diagnostics);
+    diagnostics.extend(validate_program(&program));
+    if has_errors(&diagnostics) {
+        return Err(diagnostics);
+    }
+    Ok((program, diagnostics))
+}
+
+//parse and check a reloaded source. --strict, --vm and --profile carry over
+//from `current`, and so does the seed unless the file sets its own
+pub fn load_program(source: &str, current: &Program) -> Result<Program, Vec<Diagnostic>> {
+    let (mut program, _) = check_source(source)?;
+
+    if program.evolve_block.seed.is_none() {
+        program.evolve_block.seed = current.evolve_block.seed;
+    }
+    program.strict = current.strict;
+    program.profile = current.profile;
+    if current.bytecode.is_some() {
+        program.bytecode = Some(std::sync::Arc::new(compile(&program)));
+    }
+    Ok(program)
+}
+
+//true if the two sources only differ inside their VISUALIZE blocks.
+//compares tokens, so whitespace and comment edits don't matter either
+pub fn only_visualize_changed(old: &str, new: &str) -> bool {
+    without_visualize(old) == without_visualize(new)
+}
+
+fn without_visualize(source: &str) -> Vec<TokenKind> {
+    let mut kinds = Vec::new();
+    let mut depth = 0;
+    let mut in_visualize = false;
+    for token in lexer(source) {
+        if in_visualize {
+            match token.kind {
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        in_visualize = false;
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if token.kind == TokenKind::Visualize {
+            in_visualize = true;
+            continue;
+        }
+        kinds.push(token.kind);
+    }
+    kinds
+}
@@ -0,0 +1,283 @@
+//render.rs - VISUALIZE output without a window (--render, gui Export button)
+//a small software rasterizer for DrawCmd. it draws a generation's step replay
+//into frames and writes them as numbered PNG files or one animated GIF.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+
+use crate::types::*;
+use crate::spatial::SpatialIndex;
+use crate::bytecode::Block;
+use crate::vm::run_command;
+use crate::eval::{DRAW_COMMANDS, GRID_CACHE, WORLD_DIMENSIONS, commit_moves, install_program};
+
+//same size and background as the gui canvas at zoom 1
+pub const CANVAS_SIZE: u32 = 600;
+const BACKGROUND: [u8; 3] = [20, 20, 20];
+
+//what --render gen=N[,out=PATH][,fps=F] asks for
+#[derive(Debug, Clone)]
+pub struct RenderSpec {
+    pub generation: i32,
+    pub out: Option<String>, //a .gif file, anything else is a directory of PNG frames
+    pub fps: f32,
+}
+
+impl RenderSpec {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut generation = None;
+        let mut spec = RenderSpec { generation: 0, out: None, fps: 10.0 };
+        for part in text.split(',') {
+            let (key, value) = part.split_once('=').ok_or(format!("Invalid render option '{}', expected key=value", part))?;
+            match key.trim() {
+                "gen" => {
+                    let g = value.trim().parse().ok().filter(|&g: &i32| g > 0)
+                        .ok_or(format!("Invalid render generation: {}", value))?;
+                    generation = Some(g);
+                }
+                "out" => spec.out = Some(value.trim().to_string()),
+                "fps" => {
+                    let fps = value.trim().parse().ok().filter(|&f: &f32| f > 0.0)
+                        .ok_or(format!("Invalid render fps: {}", value))?;
+                    spec.fps = fps;
+                }
+                other => return Err(format!("Unknown render option '{}' (gen, out, fps)", other)),
+            }
+        }
+        spec.generation = generation.ok_or("--render needs gen=N")?;
+        Ok(spec)
+    }
+}
+
+//rgb pixels, row by row
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&BACKGROUND);
+        }
+        Frame { width, height, pixels }
+    }
+
+    fn put(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let i = ((y as u32 * self.width + x as u32) * 3) as usize;
+        self.pixels[i..i + 3].copy_from_slice(&color);
+    }
+
+    //pixels whose centre lies in [min, max), clamped to the frame
+    fn span(&self, min: f32, max: f32, size: u32) -> (i64, i64) {
+        let from = (min - 0.5).ceil().max(0.0) as i64;
+        let to = ((max - 0.5).ceil() as i64).min(size as i64);
+        (from, to)
+    }
+
+    pub fn fill_rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: [u8; 3]) {
+        let (x0, x1) = self.span(x, x + w, self.width);
+        let (y0, y1) = self.span(y, y + h, self.height);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                self.put(px, py, color);
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, cx: f32, cy: f32, radius: f32, color: [u8; 3]) {
+        let (x0, x1) = self.span(cx - radius, cx + radius + 1.0, self.width);
+        let (y0, y1) = self.span(cy - radius, cy + radius + 1.0, self.height);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let dx = px as f32 + 0.5 - cx;
+                let dy = py as f32 + 0.5 - cy;
+                if dx * dx + dy * dy <= radius * radius {
+                    self.put(px, py, color);
+                }
+            }
+        }
+    }
+
+    //every pixel within half the thickness of the segment
+    pub fn draw_line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, thickness: f32, color: [u8; 3]) {
+        let half = (thickness / 2.0).max(0.5);
+        let (px0, px1) = self.span(x1.min(x2) - half, x1.max(x2) + half + 1.0, self.width);
+        let (py0, py1) = self.span(y1.min(y2) - half, y1.max(y2) + half + 1.0, self.height);
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let length = dx * dx + dy * dy;
+        for py in py0..py1 {
+            for px in px0..px1 {
+                let (cx, cy) = (px as f32 + 0.5, py as f32 + 0.5);
+                //closest point on the segment
+                let t = if length == 0.0 { 0.0 } else { (((cx - x1) * dx + (cy - y1) * dy) / length).clamp(0.0, 1.0) };
+                let (ex, ey) = (x1 + t * dx - cx, y1 + t * dy - cy);
+                if ex * ex + ey * ey <= half * half {
+                    self.put(px, py, color);
+                }
+            }
+        }
+    }
+
+    //draw commands in order, `unit` is the size of one VISUALIZE unit in pixels
+    pub fn draw(&mut self, commands: &[DrawCmd], unit: (f32, f32)) {
+        let (ux, uy) = unit;
+        for cmd in commands {
+            match cmd {
+                DrawCmd::Rect { x, y, w, h, r, g, b } => {
+                    self.fill_rect(x * ux, y * uy, w * ux, h * uy, [*r, *g, *b]);
+                }
+                DrawCmd::Line { x1, y1, x2, y2, r, g, b, thickness } => {
+                    self.draw_line(x1 * ux, y1 * uy, x2 * ux, y2 * uy, *thickness, [*r, *g, *b]);
+                }
+                DrawCmd::Circle { x, y, radius, r, g, b } => {
+                    self.fill_circle(x * ux, y * uy, radius * ux.min(uy), [*r, *g, *b]);
+                }
+            }
+        }
+    }
+}
+
+//pixels per VISUALIZE unit on a canvas of `size` pixels
+pub fn coordinate_unit(program: &Program, size: f32) -> (f32, f32) {
+    match program.env_coordinates {
+        Coordinates::Pixels => (1.0, 1.0),
+        Coordinates::Cells => (size / program.env_width.max(1) as f32, size / program.env_height.max(1) as f32),
+    }
+}
+
+//run the VISUALIZE block for one set of individuals, filling DRAW_COMMANDS
+pub fn run_visualize(program: &Arc<Program>, individuals: &[Individual]) -> Result<(), RuntimeError> {
+    DRAW_COMMANDS.with(|cmds| cmds.borrow_mut().clear());
+    WORLD_DIMENSIONS.with(|d| *d.borrow_mut() = (program.env_width, program.env_height));
+    install_program(program);
+
+    let viz_env = Environment::new();
+    {
+        let mut env_mut = viz_env.write().unwrap();
+        env_mut.store.insert("width".to_string(), Value::Int(program.env_width));
+        env_mut.store.insert("height".to_string(), Value::Int(program.env_height));
+    }
+
+    // set up grid cache for visualization
+    let index = SpatialIndex::build(individuals, program.env_occupancy, (program.env_width, program.env_height));
+    GRID_CACHE.with(|cache| *cache.borrow_mut() = Some(index));
+
+    //execute visualize commands
+    let mut spawner = Vec::new();
+    let mut result = Ok(());
+    for (c, cmd) in program.visualize_block.iter().enumerate() {
+        if let Err(e) = run_command(Block::Visualize, c, cmd, viz_env.clone(), individuals, &mut spawner, program) {
+            result = Err(e.within("VISUALIZE", None));
+            break;
+        }
+    }
+
+    commit_moves();
+    GRID_CACHE.with(|cache| *cache.borrow_mut() = None);
+    result
+}
+
+//one frame per recorded step, or the final state if no steps were recorded
+pub fn render_snapshot(program: &Arc<Program>, snapshot: &GenerationSnapshot) -> Result<Vec<Frame>, RuntimeError> {
+    let unit = coordinate_unit(program, CANVAS_SIZE as f32);
+    let mut steps: Vec<&[Individual]> = Vec::new();
+    for step in &snapshot.step_history {
+        steps.push(step);
+    }
+    if steps.is_empty() {
+        steps.push(&snapshot.individuals);
+    }
+
+    let mut frames = Vec::new();
+    for individuals in steps {
+        run_visualize(program, individuals)?;
+        let mut frame = Frame::new(CANVAS_SIZE, CANVAS_SIZE);
+        DRAW_COMMANDS.with(|cmds| frame.draw(&cmds.borrow(), unit));
+        frames.push(frame);
+    }
+    DRAW_COMMANDS.with(|cmds| cmds.borrow_mut().clear());
+    Ok(frames)
+}
+
+//frame_0000.png, frame_0001.png, ... in `dir`
+pub fn write_png_sequence(frames: &[Frame], dir: &str) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Can't create {}: {}", dir, e))?;
+    for (i, frame) in frames.iter().enumerate() {
+        let path = format!("{}/frame_{:04}.png", dir.trim_end_matches('/'), i);
+        let file = File::create(&path).map_err(|e| format!("Can't create {}: {}", path, e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width, frame.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| format!("Can't write {}: {}", path, e))?;
+        writer.write_image_data(&frame.pixels).map_err(|e| format!("Can't write {}: {}", path, e))?;
+        writer.finish().map_err(|e| format!("Can't write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+//animated gif that loops forever
+pub fn write_gif(frames: &[Frame], path: &str, fps: f32) -> Result<(), String> {
+    let (width, height) = match frames.first() {
+        Some(f) => (f.width as u16, f.height as u16),
+        None => return Err("Nothing to render".to_string()),
+    };
+    let file = File::create(path).map_err(|e| format!("Can't create {}: {}", path, e))?;
+    let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &[])
+        .map_err(|e| format!("Can't write {}: {}", path, e))?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("Can't write {}: {}", path, e))?;
+
+    //gif delays are in hundredths of a second
+    let delay = (100.0 / fps).round().max(1.0) as u16;
+    for frame in frames {
+        let mut gif_frame = gif_frame(frame);
+        gif_frame.delay = delay;
+        encoder.write_frame(&gif_frame).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+//drawings rarely use more than 256 colors, so the exact palette usually fits.
+//otherwise the encoder picks one by quantizing
+fn gif_frame(frame: &Frame) -> gif::Frame<'static> {
+    let mut palette: Vec<u8> = Vec::new();
+    let mut index: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut indexed = Vec::with_capacity((frame.width * frame.height) as usize);
+    for pixel in frame.pixels.chunks(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let i = match index.get(&color) {
+            Some(i) => *i,
+            None => {
+                if index.len() == 256 {
+                    return gif::Frame::from_rgb_speed(frame.width as u16, frame.height as u16, &frame.pixels, 10);
+                }
+                let i = index.len() as u8;
+                index.insert(color, i);
+                palette.extend_from_slice(&color);
+                i
+            }
+        };
+        indexed.push(i);
+    }
+    gif::Frame::from_palette_pixels(frame.width as u16, frame.height as u16, indexed, palette, None)
+}
+
+//render a generation and write it to `out`: a .gif file or a directory of PNGs.
+//returns a line describing what was written
+pub fn export(program: &Arc<Program>, snapshot: &GenerationSnapshot, out: &str, fps: f32) -> Result<String, String> {
+    let frames = render_snapshot(program, snapshot).map_err(|e| e.to_string())?;
+    if out.to_lowercase().ends_with(".gif") {
+        write_gif(&frames, out, fps)?;
+        Ok(format!("Rendered generation {} to {} ({} frames)", snapshot.generation, out, frames.len()))
+    } else {
+        write_png_sequence(&frames, out)?;
+        Ok(format!("Rendered generation {} to {}/ ({} frames)", snapshot.generation, out.trim_end_matches('/'), frames.len()))
+    }
+}
@@ -2,10 +2,19 @@
 
 use std::collections::HashMap;
 use crate::types::*;
+use crate::diagnostic::Diagnostic;
+
+//functions handled directly by the evaluator
+const BUILTINS: &[&str] = &[
+    "random", "len", "push", "pop", "get_at", "all_at", "dist", "kill",
+    "nearest", "neighbors", "count_in_radius", "moore", "von_neumann",
+    "draw_rect", "draw_line", "draw_circle",
+];
 
 #[derive(Debug, Clone, PartialEq)]
 enum Type {
     Int,
+    Float,
     String,
     Bool,
     List,
@@ -14,7 +23,8 @@ enum Type {
     Unknown,
 }
 
-pub fn validate_program(prog: &Program) -> Result<(), Vec<String>> {
+//errors and warnings, empty if the program is fine
+pub fn validate_program(prog: &Program) -> Vec<Diagnostic> {
     let mut errors = Vec::new();
     
     // 1. Map out the environment
@@ -37,21 +47,56 @@ pub fn validate_program(prog: &Program) -> Result<(), Vec<String>> {
         }
     }
 
-    //2. Validate Routines
+    if let Activation::Priority(prop) = &prog.env_activation {
+        if !known_props.contains_key(prop) {
+            errors.push(Diagnostic::error(&format!("activation priority uses unknown property '{}'", prop))
+                .context("ENVIRONMENT")
+                .note("priority(property) needs a property declared in SPECIES"));
+        }
+        if prog.env_update == UpdateMode::RandomOrder {
+            errors.push(Diagnostic::error("update: random_order can't be combined with activation: priority")
+                .context("ENVIRONMENT")
+                .note("random_order already picks the activation order, use update: sequential with priority"));
+        }
+    }
+
+    //2. Validate Functions and Routines
+    let funcs = &prog.functions_block;
+    for (name, func) in funcs {
+        if BUILTINS.contains(&name.as_str()) {
+            errors.push(Diagnostic::error("Name clashes with a built-in function").on_line(func.line)
+                .context(&format!("FUNCTION {}", name)));
+        }
+        if contains_spawn(&func.body) {
+            errors.push(Diagnostic::error("spawn is not allowed inside a FUNCTION").on_line(func.line)
+                .context(&format!("FUNCTION {}", name))
+                .note("spawn from the ROUTINE that calls the function instead"));
+        }
+        //a call starts from an empty scope: the caller's variables and the
+        //VISUALIZE width/height/steps aren't there. environment is resolved anywhere
+        let mut locals = HashMap::new();
+        locals.insert("environment".to_string(), Type::Environment);
+        locals.insert("self".to_string(), Type::Object);
+        for param in &func.params {
+            locals.insert(param.clone(), Type::Unknown);
+        }
+        check_commands(&func.body, &locals, &known_props, funcs, &mut errors, &format!("FUNCTION {}", name));
+    }
+
     for (name, routine) in &prog.routines_block {
         let mut locals = globals.clone();
         locals.insert("self".to_string(), Type::Object);
-        check_commands(&routine.body, &locals, &known_props, &mut errors, name);
+        check_commands(&routine.body, &locals, &known_props, funcs, &mut errors, &format!("ROUTINE {}", name));
     }
 
     //3. Validate Blocks
-    check_commands(&prog.spawns_block, &globals, &known_props, &mut errors, "SPAWN");
+    check_commands(&prog.spawns_block, &globals, &known_props, funcs, &mut errors, "SPAWN");
     
     //validate Fitness Block
     {
         let mut locals = globals.clone();
         locals.insert("self".to_string(), Type::Object);
-        check_commands(&prog.fitness_block.commands, &locals, &known_props, &mut errors, "FITNESS");
+        check_commands(&prog.fitness_block.commands, &locals, &known_props, funcs, &mut errors, "FITNESS");
     }
 
     for rule in &prog.mutations_block {
@@ -64,57 +109,66 @@ pub fn validate_program(prog: &Program) -> Result<(), Vec<String>> {
             } else {
                 locals.insert("self".to_string(), Type::Object);
             }
-            check_commands(body, &locals, &known_props, &mut errors, &rule.action);
+            check_commands(body, &locals, &known_props, funcs, &mut errors, &format!("MUTATE {}", rule.action));
         }
     }
 
-    if errors.is_empty() { Ok(()) } else { Err(errors) }
+    errors
 }
 
 fn check_commands(
     cmds: &[Command],
     env: &HashMap<String, Type>,
     props: &HashMap<String, Type>,
-    errors: &mut Vec<String>,
+    funcs: &HashMap<String, FunctionDef>,
+    errors: &mut Vec<Diagnostic>,
     context: &str,
 ) {
     let mut current_env = env.clone();
     for cmd in cmds {
         match cmd {
-            Command::Assign { target, value, line} => {
-                let val_type = check_exp(value, &current_env, props, errors, context);
+            Command::Assign { target, value, line: _ } => {
+                let val_type = check_exp(value, &current_env, props, funcs, errors, context);
                 match target {
                     Exp::Var(name, _) => { current_env.insert(name.clone(), val_type); }
-                    Exp::Dot(obj, field, _) => {
-                        check_exp(obj, &current_env, props, errors, context);
+                    Exp::Dot(obj, field, span) => {
+                        check_exp(obj, &current_env, props, funcs, errors, context);
                         if !props.contains_key(field) && field != "x" && field != "y" {
                             //allow dynamic creation of properties but warn in case it's a typo
-                            println!("Note: Dynamic property '{}' created on line {}.", field, line);                        }
+                            errors.push(Diagnostic::warning(&format!("Dynamic property '{}' created", field))
+                                .span(*span).context(context)
+                                .note("declare it in SPECIES if it is meant to be a property"));
+                        }
                     }
                     _ => {}
                 }
             }
             Command::If { condition, then_block, else_block, line: _ } => {
-                check_bexp(condition, &current_env, props, errors, context);
-                check_commands(then_block, &current_env, props, errors, context);
-                if let Some(eb) = else_block { check_commands(eb, &current_env, props, errors, context); }
+                check_bexp(condition, &current_env, props, funcs, errors, context);
+                check_commands(then_block, &current_env, props, funcs, errors, context);
+                if let Some(eb) = else_block { check_commands(eb, &current_env, props, funcs, errors, context); }
             }
             Command::While { condition, body, line: _ } => {
-                check_bexp(condition, &current_env, props, errors, context);
-                check_commands(body, &current_env, props, errors, context);
+                check_bexp(condition, &current_env, props, funcs, errors, context);
+                check_commands(body, &current_env, props, funcs, errors, context);
             }
             Command::Spawn { species: _, x, y, line: _ } => {
-                check_exp(x, &current_env, props, errors, context);
-                check_exp(y, &current_env, props, errors, context);
+                check_exp(x, &current_env, props, funcs, errors, context);
+                check_exp(y, &current_env, props, funcs, errors, context);
             }
             Command::Print(exps, _) => {
-                for e in exps { check_exp(e, &current_env, props, errors, context); }
+                for e in exps { check_exp(e, &current_env, props, funcs, errors, context); }
             }
             Command::Return(exp, _) => {
-                check_exp(exp, &current_env, props, errors, context);
+                check_exp(exp, &current_env, props, funcs, errors, context);
             }
             Command::Exp(exp, _) => {
-                check_exp(exp, &current_env, props, errors, context);
+                check_exp(exp, &current_env, props, funcs, errors, context);
+            }
+            Command::Die(line) => {
+                if !current_env.contains_key("self") {
+                    errors.push(Diagnostic::error("die has no 'self' to remove").on_line(*line).context(context));
+                }
             }
             Command::For { var, collection, body, line: _ } => {
                 let mut for_env = current_env.clone();
@@ -122,7 +176,7 @@ fn check_commands(
                     for_env.insert(var.clone(), Type::Object);
 
                 }
-                check_commands(body, &for_env, props, errors, context);
+                check_commands(body, &for_env, props, funcs, errors, context);
             }
         }
     }
@@ -132,56 +186,78 @@ fn check_exp(
     exp: &Exp,
     env: &HashMap<String, Type>,
     props: &HashMap<String, Type>,
-    errors: &mut Vec<String>,
+    funcs: &HashMap<String, FunctionDef>,
+    errors: &mut Vec<Diagnostic>,
     context: &str,
 ) -> Type {
     match exp {
         Exp::Int(_, _) => Type::Int,
+        Exp::Float(_, _) => Type::Float,
         Exp::StringLiteral(_, _) => Type::String,
         Exp::Bool(_, _) => Type::Bool,
-        Exp::Var(name, line) => {
+        Exp::Var(name, span) => {
             if let Some(t) = env.get(name) {
                 t.clone()
             } else if props.contains_key(name) {
                 // for built in variables x, y, species, fitness
                 props.get(name).unwrap().clone()
             } else {
-                errors.push(format!("[{}] Undefined variable: {} at line {}", context, name, line));
+                errors.push(Diagnostic::error(&format!("Undefined variable: {}", name)).span(*span).context(context));
                 Type::Unknown
             }
         }
-        Exp::BinaryOp(l, op, r, _) => {
-            let lt = check_exp(l, env, props, errors, context);
-            let rt = check_exp(r, env, props, errors, context);
+        Exp::BinaryOp(l, op, r, span) => {
+            let lt = check_exp(l, env, props, funcs, errors, context);
+            let rt = check_exp(r, env, props, funcs, errors, context);
             if op == "+" && (lt == Type::String || rt == Type::String) {
                 Type::String
             } 
             else if op != "+" && (lt == Type::String || rt == Type::String) {
-                errors.push(format!("Cannot use operator '{}' on a String", op));
+                errors.push(Diagnostic::error(&format!("Cannot use operator '{}' on a String", op)).span(*span).context(context)
+                    .note("only + works on strings, it joins them"));
                 Type::Unknown
-            }else {
+            } else if lt == Type::Float || rt == Type::Float {
+                Type::Float
+            } else {
                 Type::Int
             }
         }
         Exp::Dot(obj, field, _) => {
-            check_exp(obj, env, props, errors, context);
+            check_exp(obj, env, props, funcs, errors, context);
             props.get(field).cloned().unwrap_or(Type::Unknown)
         }
         Exp::Index(list, idx, _) => {
-            check_exp(list, env, props, errors, context);
-            check_exp(idx, env, props, errors, context);
+            check_exp(list, env, props, funcs, errors, context);
+            check_exp(idx, env, props, funcs, errors, context);
             Type::Unknown
         }
         Exp::List(items, _) => {
-            for i in items { check_exp(i, env, props, errors, context); }
+            for i in items { check_exp(i, env, props, funcs, errors, context); }
             Type::List
         }
-        Exp::Call(name, args, _) => {
-            for a in args { check_exp(a, env, props, errors, context); }
+        Exp::Call(name, args, span) => {
+            let mut arg_types = Vec::new();
+            for a in args { arg_types.push(check_exp(a, env, props, funcs, errors, context)); }
+            if let Some(func) = funcs.get(name) {
+                if func.params.len() != args.len() {
+                    errors.push(Diagnostic::error(&format!(
+                        "Function '{}' expects {} argument(s) but got {}",
+                        name, func.params.len(), args.len()
+                    )).span(*span).context(context));
+                }
+                return Type::Unknown;
+            }
             match name.as_str() {
-                "random" | "len" | "dist" => Type::Int,
-                "get_at" => Type::Object,
-                _ => Type::Unknown,
+                "random" if arg_types.contains(&Type::Float) => Type::Float,
+                "random" | "len" | "count_in_radius" => Type::Int,
+                "dist" => Type::Float,
+                "get_at" | "nearest" => Type::Object,
+                "all_at" | "neighbors" | "moore" | "von_neumann" => Type::List,
+                n if BUILTINS.contains(&n) => Type::Unknown,
+                _ => {
+                    errors.push(Diagnostic::error(&format!("Unknown function: {}", name)).span(*span).context(context));
+                    Type::Unknown
+                }
             }
         }
     }
@@ -191,18 +267,19 @@ fn check_bexp(
     bexp: &BExp,
     env: &HashMap<String, Type>,
     props: &HashMap<String, Type>,
-    errors: &mut Vec<String>,
+    funcs: &HashMap<String, FunctionDef>,
+    errors: &mut Vec<Diagnostic>,
     context: &str,
 ) {
     match bexp {
         BExp::Equal(l, r) | BExp::NotEqual(l, r) | BExp::Greater(l, r) | 
         BExp::Less(l, r) | BExp::GreaterEqual(l, r) | BExp::LessEqual(l, r) => {
-            check_exp(l, env, props, errors, context);
-            check_exp(r, env, props, errors, context); //2 exps
+            check_exp(l, env, props, funcs, errors, context);
+            check_exp(r, env, props, funcs, errors, context); //2 exps
         }
         BExp::And(l, r) | BExp::Or(l, r) => {
-            check_bexp(l, env, props, errors, context); //2 bexps
-            check_bexp(r, env, props, errors, context);
+            check_bexp(l, env, props, funcs, errors, context); //2 bexps
+            check_bexp(r, env, props, funcs, errors, context);
         }
     }
 }
@@ -210,10 +287,30 @@ fn check_bexp(
 fn infer_type(exp: &Exp) -> Type {
     match exp {
         Exp::Int(..) => Type::Int,
+        Exp::Float(..) => Type::Float,
         Exp::StringLiteral(..) => Type::String,
         Exp::Bool(..) => Type::Bool,
         Exp::List(..) => Type::List,
-        Exp::Call(name, _, _) if name == "get_at" => Type::Object,
+        Exp::Call(name, _, _) if name == "get_at" || name == "nearest" => Type::Object,
+        Exp::Call(name, _, _) if matches!(name.as_str(), "all_at" | "neighbors" | "moore" | "von_neumann") => Type::List,
         _ => Type::Unknown,
     }
 }
+
+//spawn needs the world's spawner, which FUNCTION calls don't have
+fn contains_spawn(cmds: &[Command]) -> bool {
+    for cmd in cmds {
+        let found = match cmd {
+            Command::Spawn { .. } => true,
+            Command::If { then_block, else_block, .. } => {
+                contains_spawn(then_block) || else_block.as_ref().is_some_and(|eb| contains_spawn(eb))
+            }
+            Command::While { body, .. } | Command::For { body, .. } => contains_spawn(body),
+            _ => false,
+        };
+        if found {
+            return true;
+        }
+    }
+    false
+}
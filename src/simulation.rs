@@ -0,0 +1,326 @@
+//simulation.rs - the evolution loop without any gui
+//owns the world instances and the generation history so the same program
+//runs identically in the egui window and in the headless batch runner.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use rayon::prelude::*;
+
+use crate::types::*;
+use crate::stats::{GenerationStats, StatsWriter};
+use crate::profiler::Profile;
+use crate::checkpoint;
+use crate::evolution::{
+    snapshot_individuals, create_next_generation,
+    clear_snapshot_memory, clear_world_history
+};
+
+//how many generation snapshots are kept around for replay
+const MAX_HISTORY: usize = 100;
+
+//summary of one finished generation
+#[derive(Debug, Clone)]
+pub struct GenerationReport {
+    pub generation: i32,
+    pub avg_fitness: i32,
+    pub best_fitness: i32,
+    pub duration: Duration,
+    pub history_trimmed: bool, //true if the oldest snapshot was dropped to make room
+}
+
+impl fmt::Display for GenerationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[Gen {}] Avg: {}, Best: {} (took {:?})",
+            self.generation, self.avg_fitness, self.best_fitness, self.duration)
+    }
+}
+
+pub struct Simulation {
+    pub instances: Vec<World>,
+    pub history: Vec<GenerationSnapshot>,
+    pub program: Arc<Program>,
+    pub num_generations: i32,
+    pub num_instances: i32,
+    pub current_gen: i32,
+    pub global_best_fitness: i32,
+    pub record_steps: bool, //keep per-step replays of the best instance (only the gui needs them)
+    pub stats_out: Option<StatsWriter>, //--stats-out file, gets a record every generation
+    pub checkpoint_path: Option<String>, //where periodic checkpoints are written
+    pub checkpoint_every: i32,           //save a checkpoint every n generations (0 = never)
+    pub error: Option<RuntimeError>,     //strict mode runtime error that stopped the run
+    pub warned: Vec<RuntimeError>,       //warnings printed so far, see report_warnings
+    pub profile: Profile,                //timings of all worlds so far (--profile)
+}
+
+impl Simulation {
+    pub fn new(program: Arc<Program>) -> Self {
+        let mut sim = Self::without_worlds(program);
+        sim.spawn_instances();
+        sim
+    }
+
+    //a simulation whose worlds are put in afterwards (a checkpoint), so
+    //SPAWN doesn't run for nothing
+    pub fn without_worlds(program: Arc<Program>) -> Self {
+        Self {
+            instances: Vec::new(),
+            history: Vec::new(),
+            num_generations: program.evolve_block.generations,
+            num_instances: program.evolve_block.instances,
+            program,
+            current_gen: 0,
+            global_best_fitness: 0,
+            record_steps: true,
+            stats_out: None,
+            checkpoint_path: None,
+            checkpoint_every: 0,
+            error: None,
+            warned: Vec::new(),
+            profile: Profile::default(),
+        }
+    }
+
+    //create fresh world instances from the SPAWN block
+    fn spawn_instances(&mut self) {
+        let mut instances = Vec::new();
+        for i in 0..self.num_instances {
+            let mut w = World::new(self.program.clone(), i);
+            w.spawn();
+            instances.push(w);
+        }
+        self.instances = instances;
+        self.report_warnings();
+        self.check_errors();
+    }
+
+    //add up what every world recorded since the last time
+    fn collect_profiles(&mut self) {
+        for w in &mut self.instances {
+            self.profile.merge(std::mem::take(&mut w.profile));
+        }
+    }
+
+    //print what the worlds ran into outside strict mode, once they are done
+    //running in parallel. each warning is printed once per run
+    fn report_warnings(&mut self) {
+        for w in &mut self.instances {
+            for warning in std::mem::take(&mut w.warnings) {
+                if !self.warned.iter().any(|e| e.line == warning.line && e.message == warning.message) {
+                    println!("{}", warning.as_warning());
+                    self.warned.push(warning);
+                }
+            }
+        }
+    }
+
+    //stop the run on the first runtime error any world ran into
+    fn check_errors(&mut self) -> bool {
+        if self.error.is_none() {
+            self.error = self.instances.iter_mut().find_map(|w| w.error.take());
+        }
+        self.error.is_some()
+    }
+
+    //check the EVOLVE settings before starting a run
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_instances == 0 {
+            return Err("No instances defined in EVOLVE block.".to_string());
+        }
+        if self.num_generations == 0 {
+            return Err("No generations defined in EVOLVE block.".to_string());
+        }
+        let settings = &self.program.evolve_block;
+        if settings.elitism < 0 || settings.elitism > self.num_instances {
+            return Err(format!("elitism must be between 0 and {} (the number of instances).", self.num_instances));
+        }
+        let survivors = settings.survivor_count();
+        if survivors < 1 || survivors > self.num_instances {
+            return Err(format!("survivors must be between 1 and {} (the number of instances).", self.num_instances));
+        }
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current_gen >= self.num_generations
+    }
+
+    //run one generation of evolution, returns None once all generations are done
+    pub fn run_generation(&mut self) -> Option<GenerationReport> {
+        let start = std::time::Instant::now();
+
+        if self.is_finished() || self.instances.is_empty() || self.error.is_some() {
+            return None;
+        }
+
+        self.current_gen += 1;
+        let g = self.current_gen;
+
+        //run simulation steps in parallel using Rayon
+        let env_steps = self.program.env_steps;
+
+        //enable history recording
+        for w in &mut self.instances {
+            w.record_history = self.record_steps;
+            w.history.clear();
+        }
+
+        self.instances.par_iter_mut().for_each(|world| {
+            world.generation = g;
+            for _ in 0..env_steps {
+                world.step();
+            }
+            //capture final state as a snapshot
+            if world.record_history {
+                let mut final_snapshot = Vec::new();
+                for ind in &world.individuals {
+                    let cloned_ind = ind.deep_clone();
+                    final_snapshot.push(cloned_ind);
+                }
+                world.history.push(final_snapshot);
+            }
+            world.calculate_total_fitness();
+        });
+        self.collect_profiles();
+
+        //a broken generation isn't recorded: its fitness numbers can't be trusted
+        self.report_warnings();
+        if self.check_errors() {
+            clear_world_history(&mut self.instances);
+            return None;
+        }
+
+        //sort by fitness
+        let mut indices: Vec<usize> = Vec::new();
+        for i in 0..self.instances.len() {
+            indices.push(i);
+        }
+        indices.sort_by_key(|&i| -self.instances[i].fitness);
+
+        let mut sorted_instances = Vec::new();
+        for &i in &indices {
+            sorted_instances.push(self.instances[i].take());
+        }
+        self.instances = sorted_instances;
+
+        //extract history from the best instance
+        let raw_history = std::mem::take(&mut self.instances[0].history);
+        let mut best_history = Vec::new();
+        for step_individuals in raw_history {
+            let step_snapshot = snapshot_individuals(&step_individuals, &self.program);
+            //break reference cycles in the original heavy data
+            for ind in step_individuals {
+                ind.env.write().unwrap().store.clear();
+            }
+            best_history.push(step_snapshot);
+        }
+
+        //clear history from other instances
+        clear_world_history(&mut self.instances);
+
+        // record statistics
+        let mut total_fitness: i32 = 0;
+        for w in &self.instances {
+            total_fitness += w.fitness;
+        }
+        let avg = total_fitness / self.num_instances.max(1);
+        let best = self.instances[0].fitness;
+        let worst = self.instances[self.instances.len() - 1].fitness;
+        if best > self.global_best_fitness {
+            self.global_best_fitness = best;
+        }
+
+        //store snapshot for visualization
+        let snapshot = GenerationSnapshot {
+            generation: g,
+            avg_fitness: avg,
+            best_fitness: best,
+            worst_fitness: worst,
+            individuals: snapshot_individuals(&self.instances[0].individuals, &self.program),
+            step_history: best_history,
+        };
+        self.history.push(snapshot);
+
+        //limit history to prevent memory leak
+        // rip my laptop learned from experience </3
+        let mut history_trimmed = false;
+        if self.history.len() > MAX_HISTORY {
+            let removed = self.history.remove(0);
+            clear_snapshot_memory(&removed);
+            history_trimmed = true;
+        }
+
+        let duration = start.elapsed();
+        let stats = GenerationStats::collect(g, &self.instances, &self.program, duration);
+        if let Some(writer) = &mut self.stats_out
+            && let Err(e) = writer.record(&stats) {
+            println!("Error writing stats: {} (stats export stopped)", e);
+            self.stats_out = None;
+        }
+
+        //create next generation
+        self.instances = create_next_generation(
+            &mut self.instances,
+            &self.program,
+            self.num_instances,
+            self.current_gen,
+        );
+        self.collect_profiles();
+        //mutation/crossover errors end the run after this generation's report
+        self.report_warnings();
+        self.check_errors();
+
+        //checkpoints are taken between generations so a resume picks up right here
+        if self.checkpoint_every > 0 && g % self.checkpoint_every == 0 && self.error.is_none()
+            && let Some(path) = &self.checkpoint_path {
+            match checkpoint::save(self, path) {
+                Ok(()) => println!("Checkpoint saved to {} (gen {})", path, g),
+                Err(e) => println!("Error saving checkpoint: {}", e),
+            }
+        }
+
+        Some(GenerationReport {
+            generation: g,
+            avg_fitness: avg,
+            best_fitness: best,
+            duration,
+            history_trimmed,
+        })
+    }
+
+    //swap in a reloaded program that only differs in VISUALIZE.
+    //the worlds keep their individuals and the history stays
+    pub fn replace_program(&mut self, program: Arc<Program>) {
+        for w in &mut self.instances {
+            w.program = program.clone();
+        }
+        self.program = program;
+    }
+
+    //start over from generation 0 with a reloaded program
+    pub fn restart(&mut self, program: Arc<Program>) {
+        self.num_generations = program.evolve_block.generations;
+        self.num_instances = program.evolve_block.instances;
+        self.program = program;
+        self.reset();
+    }
+
+    //reset to initial state
+    pub fn reset(&mut self) {
+        self.current_gen = 0;
+        for removed in &self.history {
+            clear_snapshot_memory(removed);
+        }
+        self.history.clear();
+        self.global_best_fitness = 0;
+        self.error = None;
+        self.warned.clear();
+        self.profile.clear();
+        if let Some(writer) = &mut self.stats_out
+            && let Err(e) = writer.restart(&self.program) {
+            println!("Error writing stats: {} (stats export stopped)", e);
+            self.stats_out = None;
+        }
+        self.spawn_instances();
+    }
+}
@@ -0,0 +1,195 @@
+//spatial.rs - which individuals stand on which cell
+//built at the start of a step (and for fitness / visualize) and kept up to date
+//when code assigns to x or y, so get_at, all_at and environment[x][y] always
+//see every occupant of a cell. also answers the neighborhood builtins
+//(nearest, neighbors, count_in_radius, moore, von_neumann) without looping
+//over the whole population.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::types::*;
+
+//how distance is measured for a neighborhood query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Manhattan, //|dx| + |dy|, a diamond (von neumann neighborhood)
+    Chebyshev, //max(|dx|, |dy|), a square (moore neighborhood)
+}
+
+//cells are keyed by wrapped position, so x = -1 and x = width - 1 share one.
+//x and y themselves are left as the program set them
+pub struct SpatialIndex {
+    cells: HashMap<(i32, i32), Vec<Arc<RwLock<Environment>>>>,
+    occupancy: Occupancy,
+    dims: (i32, i32), //world width and height, never 0
+    moved: Vec<(Arc<RwLock<Environment>>, Value, Value)>, //moved since the last commit, with the x and y they started from
+}
+
+impl SpatialIndex {
+    pub fn build(individuals: &[Individual], occupancy: Occupancy, dims: (i32, i32)) -> Self {
+        let mut index = Self { cells: HashMap::new(), occupancy, dims: (dims.0.max(1), dims.1.max(1)), moved: Vec::new() };
+        for ind in individuals {
+            if let Some(pos) = position(&ind.env.read().unwrap()) {
+                let key = index.wrap(pos);
+                index.cells.entry(key).or_default().push(ind.env.clone());
+            }
+        }
+        index
+    }
+
+    //the cell a position falls on once the world wraps around
+    pub fn wrap(&self, pos: (i32, i32)) -> (i32, i32) {
+        (pos.0.rem_euclid(self.dims.0), pos.1.rem_euclid(self.dims.1))
+    }
+
+    //everyone still alive at exactly (x, y), in world order
+    pub fn alive_at(&self, x: i32, y: i32) -> Vec<Arc<RwLock<Environment>>> {
+        let mut found = Vec::new();
+        if let Some(cell) = self.cells.get(&self.wrap((x, y))) {
+            for env in cell {
+                let env_b = env.read().unwrap();
+                if !env_b.dead && position(&env_b) == Some((x, y)) {
+                    found.push(env.clone());
+                }
+            }
+        }
+        found
+    }
+
+    //the first living individual at exactly (x, y)
+    pub fn first_alive_at(&self, x: i32, y: i32) -> Option<Arc<RwLock<Environment>>> {
+        self.cells.get(&self.wrap((x, y)))?
+            .iter()
+            .find(|env| {
+                let env_b = env.read().unwrap();
+                !env_b.dead && position(&env_b) == Some((x, y))
+            })
+            .cloned()
+    }
+
+    //the first living occupant of the cell (x, y) wraps onto, like environment[x][y]
+    pub fn first_alive_in_cell(&self, x: i32, y: i32) -> Option<Arc<RwLock<Environment>>> {
+        self.cells.get(&self.wrap((x, y)))?
+            .iter()
+            .find(|env| !env.read().unwrap().dead)
+            .cloned()
+    }
+
+    //would the occupancy policy stop someone from moving onto this cell
+    pub fn blocks(&self, to: (i32, i32)) -> bool {
+        self.is_blocking() && self.first_alive_in_cell(to.0, to.1).is_some()
+    }
+
+    //does the occupancy policy keep individuals apart at all
+    pub fn is_blocking(&self) -> bool {
+        self.occupancy == Occupancy::Block
+    }
+
+    //is the cell `at` wraps onto taken by someone alive other than `env`
+    pub fn taken_by_other(&self, env: &Arc<RwLock<Environment>>, at: (i32, i32)) -> bool {
+        self.cells.get(&self.wrap(at)).is_some_and(|cell| {
+            cell.iter().any(|e| !Arc::ptr_eq(e, env) && !e.read().unwrap().dead)
+        })
+    }
+
+    //remember where an individual started from before its x or y changes.
+    //only the first change since the last commit counts
+    pub fn note_move(&mut self, env: &Arc<RwLock<Environment>>) {
+        if self.moved.iter().any(|(e, _, _)| Arc::ptr_eq(e, env)) {
+            return;
+        }
+        let env_b = env.read().unwrap();
+        let x = env_b.store.get("x").cloned().unwrap_or(Value::Int(0));
+        let y = env_b.store.get("y").cloned().unwrap_or(Value::Int(0));
+        drop(env_b);
+        self.moved.push((env.clone(), x, y));
+    }
+
+    //everyone noted since the last commit, see eval::commit_moves
+    pub fn take_moves(&mut self) -> Vec<(Arc<RwLock<Environment>>, Value, Value)> {
+        std::mem::take(&mut self.moved)
+    }
+
+    //move an individual's entry after its x or y changed. the occupancy
+    //policy isn't checked here, only once the whole move is done
+    pub fn relocate(&mut self, env: &Arc<RwLock<Environment>>, from: (i32, i32), to: (i32, i32)) {
+        let (from, to) = (self.wrap(from), self.wrap(to));
+        let indexed = self.cells.get(&from).is_some_and(|cell| cell.iter().any(|e| Arc::ptr_eq(e, env)));
+        if !indexed || from == to {
+            return;
+        }
+        if let Some(cell) = self.cells.get_mut(&from) {
+            cell.retain(|e| !Arc::ptr_eq(e, env));
+            if cell.is_empty() {
+                self.cells.remove(&from);
+            }
+        }
+        self.cells.entry(to).or_default().push(env.clone());
+    }
+
+    //everyone alive within `radius` of `center`, paired with their distance.
+    //the world wraps around like environment[x][y] does, so an
+    //individual at x = 0 is next to one at x = width - 1.
+    //sorted nearest first, ties by row then column so results don't depend on hash order
+    pub fn within(&self, center: (i32, i32), radius: i32, metric: Metric) -> Vec<(i32, Arc<RwLock<Environment>>)> {
+        let (width, height) = self.dims;
+        if radius < 0 {
+            return Vec::new();
+        }
+        let (cx, cy) = self.wrap(center);
+
+        //look at the cells around the center when that is cheaper than
+        //looking at every occupied cell (and the square doesn't wrap onto itself)
+        let mut cells = Vec::new();
+        let side = 2 * radius as i64 + 1;
+        if side <= width as i64 && side <= height as i64 && ((side * side) as usize) < self.cells.len() {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let key = ((cx + dx).rem_euclid(width), (cy + dy).rem_euclid(height));
+                    if let Some(cell) = self.cells.get(&key) {
+                        cells.push((key, cell));
+                    }
+                }
+            }
+        } else {
+            for (key, cell) in &self.cells {
+                cells.push((*key, cell));
+            }
+        }
+
+        let mut found = Vec::new();
+        for (key, cell) in cells {
+            let (x, y) = key;
+            let dx = (x - cx).abs().min(width - (x - cx).abs());
+            let dy = (y - cy).abs().min(height - (y - cy).abs());
+            let distance = match metric {
+                Metric::Manhattan => dx + dy,
+                Metric::Chebyshev => dx.max(dy),
+            };
+            if distance > radius {
+                continue;
+            }
+            for env in cell {
+                if !env.read().unwrap().dead {
+                    found.push((distance, (y, x), env.clone()));
+                }
+            }
+        }
+
+        //stable, so occupants of one cell keep their order
+        found.sort_by_key(|(distance, cell, _)| (*distance, *cell));
+        found.into_iter().map(|(distance, _, env)| (distance, env)).collect()
+    }
+}
+
+//cell an individual is standing on (floats are truncated to their cell)
+pub fn position(env: &Environment) -> Option<(i32, i32)> {
+    let x = env.store.get("x")?;
+    let y = env.store.get("y")?;
+    if matches!(x, Value::Int(_) | Value::Float(_)) && matches!(y, Value::Int(_) | Value::Float(_)) {
+        Some((x.to_int(), y.to_int()))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,225 @@
+//stats.rs - per-generation statistics and the --stats-out file writer
+//the writer picks csv or json from the file extension so runs can be
+//plotted and compared in external tools.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::types::*;
+
+//fitness summary of one generation across all its instances
+#[derive(Debug, Clone, Default)]
+pub struct GenerationStats {
+    pub generation: i32,
+    pub instances: usize,
+    pub min_fitness: i32,
+    pub avg_fitness: f64,
+    pub median_fitness: f64,
+    pub max_fitness: i32,
+    pub stddev_fitness: f64,
+    pub population: BTreeMap<String, usize>, //individuals per species, summed over all instances
+    pub duration: Duration,
+}
+
+impl GenerationStats {
+    //collect stats from worlds whose fitness has already been calculated
+    pub fn collect(generation: i32, instances: &[World], program: &Program, duration: Duration) -> Self {
+        let mut stats = GenerationStats {
+            generation,
+            instances: instances.len(),
+            duration,
+            ..Default::default()
+        };
+
+        //every species gets a column even if it died out
+        for name in program.species_block.keys() {
+            stats.population.insert(name.clone(), 0);
+        }
+        for w in instances {
+            for ind in &w.individuals {
+                *stats.population.entry(ind.species.clone()).or_insert(0) += 1;
+            }
+        }
+
+        if instances.is_empty() {
+            return stats;
+        }
+
+        let mut scores: Vec<i32> = instances.iter().map(|w| w.fitness).collect();
+        scores.sort();
+        let n = scores.len();
+
+        stats.min_fitness = scores[0];
+        stats.max_fitness = scores[n - 1];
+        stats.avg_fitness = scores.iter().map(|&s| s as f64).sum::<f64>() / n as f64;
+        stats.median_fitness = if n.is_multiple_of(2) {
+            (scores[n / 2 - 1] as f64 + scores[n / 2] as f64) / 2.0
+        } else {
+            scores[n / 2] as f64
+        };
+
+        let variance = scores.iter()
+            .map(|&s| (s as f64 - stats.avg_fitness).powi(2))
+            .sum::<f64>() / n as f64;
+        stats.stddev_fitness = variance.sqrt();
+
+        stats
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StatsFormat {
+    Csv,
+    Json,
+}
+
+//writes one record per generation, flushed as it goes so
+//an interrupted run still leaves a readable file
+pub struct StatsWriter {
+    file: File,
+    format: StatsFormat,
+    species: Vec<String>, //fixed column order for the csv header
+    records: usize,
+}
+
+impl StatsWriter {
+    //start the file over, or when resuming after generation `resume_after`
+    //keep the records up to it so the file continues where the checkpoint was taken
+    pub fn open(path: &str, program: &Program, resume_after: Option<i32>) -> Result<Self, String> {
+        let format = if path.ends_with(".csv") {
+            StatsFormat::Csv
+        } else if path.ends_with(".json") {
+            StatsFormat::Json
+        } else {
+            return Err(format!("Stats file must end in .csv or .json: {}", path));
+        };
+
+        //a missing file just starts empty
+        let old = match resume_after {
+            Some(_) => std::fs::read_to_string(path).unwrap_or_default(),
+            None => String::new(),
+        };
+        let kept = match resume_after {
+            Some(generation) => kept_records(&old, format, generation),
+            None => Vec::new(),
+        };
+
+        let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path, e))?;
+        let mut species: Vec<String> = program.species_block.keys().cloned().collect();
+        species.sort();
+
+        let mut writer = StatsWriter { file, format, species, records: 0 };
+        writer.write_records(&kept).map_err(|e| format!("Cannot write {}: {}", path, e))?;
+        Ok(writer)
+    }
+
+    //header plus records that were already written, one per line
+    fn write_records(&mut self, records: &[&str]) -> std::io::Result<()> {
+        match self.format {
+            StatsFormat::Csv => {
+                self.write_header()?;
+                for record in records {
+                    writeln!(self.file, "{}", record)?;
+                }
+            }
+            StatsFormat::Json if records.is_empty() => self.write_header()?,
+            StatsFormat::Json => write!(self.file, "[\n{}\n]\n", records.join(",\n"))?,
+        }
+        self.records = records.len();
+        self.file.flush()
+    }
+
+    //empty the file again when the simulation starts over, so it never holds
+    //two runs. the species are taken again from the (maybe reloaded) program
+    pub fn restart(&mut self, program: &Program) -> std::io::Result<()> {
+        self.species = program.species_block.keys().cloned().collect();
+        self.species.sort();
+        self.records = 0;
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        match self.format {
+            StatsFormat::Csv => {
+                let mut header = String::from("generation,instances,min,avg,median,max,stddev,time_ms");
+                for name in &self.species {
+                    header.push_str(&format!(",pop_{}", name));
+                }
+                writeln!(self.file, "{}", header)
+            }
+            //an empty array that every record is spliced into
+            StatsFormat::Json => write!(self.file, "[\n]\n"),
+        }
+    }
+
+    pub fn record(&mut self, stats: &GenerationStats) -> std::io::Result<()> {
+        let time_ms = stats.duration.as_secs_f64() * 1000.0;
+        match self.format {
+            StatsFormat::Csv => {
+                let mut row = format!(
+                    "{},{},{},{},{},{},{},{}",
+                    stats.generation, stats.instances, stats.min_fitness, stats.avg_fitness,
+                    stats.median_fitness, stats.max_fitness, stats.stddev_fitness, time_ms
+                );
+                for name in &self.species {
+                    row.push_str(&format!(",{}", stats.population.get(name).copied().unwrap_or(0)));
+                }
+                writeln!(self.file, "{}", row)?;
+            }
+            StatsFormat::Json => {
+                let mut population = Vec::new();
+                for (name, count) in &stats.population {
+                    population.push(format!("\"{}\": {}", escape_json(name), count));
+                }
+                let record = format!(
+                    "{{\"generation\": {}, \"instances\": {}, \"min\": {}, \"avg\": {}, \"median\": {}, \"max\": {}, \"stddev\": {}, \"time_ms\": {}, \"population\": {{{}}}}}",
+                    stats.generation, stats.instances, stats.min_fitness, stats.avg_fitness,
+                    stats.median_fitness, stats.max_fitness, stats.stddev_fitness, time_ms,
+                    population.join(", ")
+                );
+
+                //overwrite the closing bracket, then put it back after the new record
+                if self.records == 0 {
+                    self.file.seek(SeekFrom::End(-2))?;
+                    write!(self.file, "  {}\n]\n", record)?;
+                } else {
+                    self.file.seek(SeekFrom::End(-3))?;
+                    write!(self.file, ",\n  {}\n]\n", record)?;
+                }
+            }
+        }
+        self.records += 1;
+        self.file.flush()
+    }
+}
+
+//the records of an earlier file up to and including `generation`, as written
+//(one per line: csv rows after the header, json objects inside the array)
+fn kept_records(old: &str, format: StatsFormat, generation: i32) -> Vec<&str> {
+    let mut kept = Vec::new();
+    for line in old.lines() {
+        let record = match format {
+            StatsFormat::Csv => line,
+            StatsFormat::Json => line.trim().trim_end_matches(','),
+        };
+        let number = match format {
+            StatsFormat::Csv => record.split(',').next(),
+            StatsFormat::Json => record.strip_prefix("{\"generation\": ").and_then(|r| r.split(',').next()),
+        };
+        if let Some(g) = number.and_then(|n| n.parse::<i32>().ok())
+            && g <= generation {
+            kept.push(record);
+        }
+    }
+    kept
+}
+
+//species names are identifiers, but quote-escape anyway to be safe
+fn escape_json(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -4,6 +4,12 @@
 
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng; //same generator as StdRng, but its position can be saved
+
+use crate::bytecode::Bytecode;
+use crate::profiler::Profile;
 
 //environment - stores variables for each individual/scope
 //think of this like a "box" that holds named values.
@@ -11,17 +17,22 @@ use std::sync::{Arc, RwLock};
 
 #[derive(Debug)]
 pub struct Environment {
-    pub store: HashMap<String, Value>,
+    pub store: Store,
+    pub dead: bool, //marked by die/kill, removed from the world at the end of the step
 }
 
 impl Environment {
     pub fn new() -> Arc<RwLock<Self>> {
-        Arc::new(RwLock::new(Self { store: HashMap::new() }))
+        Self::with_store(Store::default())
+    }
+
+    pub fn with_store(store: Store) -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(Self { store, dead: false }))
     }
 
-    pub fn deep_copy_store(&self) -> HashMap<String, Value> {
-        //1. initialize the new container
-        let mut new_store = HashMap::new();
+    pub fn deep_copy_store(&self) -> Store {
+        //1. initialize the new container, with the same slots
+        let mut new_store = self.store.empty_like();
 
         //2. explicitly loop through the current store
         for (key, value) in self.store.iter() {
@@ -38,6 +49,163 @@ impl Environment {
     }
 }
 
+//layout - the slot number of every name a compiled program uses (bytecode.rs).
+//one layout per program, shared by all species: FITNESS, MUTATE and FUNCTIONs
+//run on any species and still find each property in the same slot
+#[derive(Debug)]
+pub struct Layout {
+    pub names: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl Layout {
+    //slots every layout starts with
+    pub const SELF: usize = 0;
+    pub const X: usize = 1;
+    pub const Y: usize = 2;
+    pub const SPECIES: usize = 3;
+    pub const ENVIRONMENT: usize = 4;
+
+    //slot of a name, added at the end the first time
+    pub fn add(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.index.get(name) {
+            return slot;
+        }
+        self.names.push(name.to_string());
+        self.index.insert(name.to_string(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        let mut layout = Layout { names: Vec::new(), index: HashMap::new() };
+        for name in ["self", "x", "y", "species", "environment"] {
+            layout.add(name);
+        }
+        layout
+    }
+}
+
+//store - the variables of an environment. names in the layout live in a slot
+//the vm reads by number, other names (or a store without a layout, like the
+//tree-walker's) are kept by name
+#[derive(Debug, Default)]
+pub struct Store {
+    layout: Option<Arc<Layout>>,
+    values: Vec<Option<Value>>,
+    extra: HashMap<String, Value>, //names outside the layout
+}
+
+impl Store {
+    pub fn with_layout(layout: &Arc<Layout>) -> Self {
+        Self { layout: Some(layout.clone()), values: vec![None; layout.names.len()], extra: HashMap::new() }
+    }
+
+    //an empty store with the same slots
+    pub fn empty_like(&self) -> Self {
+        match &self.layout {
+            Some(layout) => Self::with_layout(layout),
+            None => Self::default(),
+        }
+    }
+
+    //move everything into the slots of `layout` (a checkpoint, loaded before the program is known)
+    pub fn adopt(&mut self, layout: &Arc<Layout>) {
+        let old = std::mem::replace(self, Self::with_layout(layout));
+        for (name, value) in old.into_pairs() {
+            self.insert(name, value);
+        }
+    }
+
+    fn into_pairs(self) -> Vec<(String, Value)> {
+        let mut pairs = Vec::new();
+        if let Some(layout) = &self.layout {
+            for (name, value) in layout.names.iter().zip(self.values) {
+                if let Some(value) = value {
+                    pairs.push((name.clone(), value));
+                }
+            }
+        }
+        pairs.extend(self.extra);
+        pairs
+    }
+
+    fn slot_of(&self, name: &str) -> Option<usize> {
+        self.layout.as_ref().and_then(|layout| layout.slot(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self.slot_of(name) {
+            Some(slot) => self.values[slot].as_ref(),
+            None => self.extra.get(name),
+        }
+    }
+
+    pub fn insert(&mut self, name: String, value: Value) {
+        match self.slot_of(&name) {
+            Some(slot) => self.values[slot] = Some(value),
+            None => { self.extra.insert(name, value); }
+        }
+    }
+
+    //read a slot of `layout`. by name when this store has other slots
+    //(made by another compile of the program, or without a layout)
+    pub fn slot(&self, layout: &Arc<Layout>, slot: usize) -> Option<&Value> {
+        match &self.layout {
+            Some(own) if Arc::ptr_eq(own, layout) => self.values[slot].as_ref(),
+            _ => self.get(&layout.names[slot]),
+        }
+    }
+
+    pub fn set_slot(&mut self, layout: &Arc<Layout>, slot: usize, value: Value) {
+        match &self.layout {
+            Some(own) if Arc::ptr_eq(own, layout) => self.values[slot] = Some(value),
+            _ => self.insert(layout.names[slot].clone(), value),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.values.iter_mut().for_each(|value| *value = None);
+        self.extra.clear();
+    }
+
+    //slots in layout order, then the other names
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        let names = self.layout.iter().flat_map(|layout| layout.names.iter());
+        names.zip(self.values.iter())
+            .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+            .chain(self.extra.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Value)> {
+        let names = self.layout.iter().flat_map(|layout| layout.names.iter());
+        names.zip(self.values.iter_mut())
+            .filter_map(|(name, value)| value.as_mut().map(|value| (name, value)))
+            .chain(self.extra.iter_mut())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(name, _)| name)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Value> {
+        self.iter_mut().map(|(_, value)| value)
+    }
+}
+
+impl std::ops::Index<&String> for Store {
+    type Output = Value;
+
+    fn index(&self, name: &String) -> &Value {
+        self.get(name).expect("no such variable")
+    }
+}
+
 //value - what variables can hold
 //a value is anything that can be stored in a variable:
 //numbers, text, lists, or references to other environments.
@@ -45,6 +213,7 @@ impl Environment {
 #[derive(Debug, Clone)]
 pub enum Value {
     Int(i32),                           //a whole number: 42
+    Float(f64),                         //a decimal number: 0.25
     Bool(bool),                         //true or false
     String(String),                     //text: "hello"
     Object(Arc<RwLock<Environment>>),   //reference to another creature
@@ -55,25 +224,66 @@ pub enum Value {
 
 impl Value {
     //convert any value to an integer (for math operations)
+    #[allow(clippy::collapsible_match)]
     pub fn to_int(&self) -> i32 {
         match self {
             Value::Int(v) => *v,
+            Value::Float(f) => *f as i32,
             Value::Bool(b) => if *b { 1 } else { 0 },
             Value::String(s) => s.parse().unwrap_or(0),
             _ => 0,
         }
     }
 
-    //convert any value to a string for printing
-    pub fn to_string(&self) -> String {
+    //convert any value to a float (for mixed int/float math)
+    pub fn to_float(&self) -> f64 {
         match self {
-            Value::Int(v) => v.to_string(),
-            Value::Bool(b) => b.to_string(),
-            Value::String(s) => s.clone(),
-            Value::Object(_) => "[Object]".to_string(),
-            Value::List(l) => format!("{:?}", l.read().unwrap()),
-            Value::Environment => "[Environment]".to_string(),
-            Value::GridRow(x) => format!("[GridRow {}]", x),
+            Value::Float(f) => *f,
+            Value::String(s) => s.parse().unwrap_or(0.0),
+            _ => self.to_int() as f64,
+        }
+    }
+
+    //keep floats as floats, turn everything else into an int
+    pub fn to_number(&self) -> Value {
+        match self {
+            Value::Float(f) => Value::Float(*f),
+            _ => Value::Int(self.to_int()),
+        }
+    }
+
+    //name of the value's type for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "an Int",
+            Value::Float(_) => "a Float",
+            Value::Bool(_) => "a Bool",
+            Value::String(_) => "a String",
+            Value::Object(_) => "an Object",
+            Value::List(_) => "a List",
+            Value::Environment => "the environment",
+            Value::GridRow(_) => "a grid row",
+        }
+    }
+
+    //short form for the debugger and the inspector: objects by species and
+    //position instead of their whole store (which also stops self-references)
+    pub fn describe(&self) -> String {
+        match self {
+            Value::Object(obj) => {
+                let obj = obj.read().unwrap();
+                let species = obj.store.get("species").map_or("Object".to_string(), |v| v.to_string());
+                match (obj.store.get("x"), obj.store.get("y")) {
+                    (Some(x), Some(y)) => format!("<{} at ({}, {})>", species, x, y),
+                    _ => format!("<{}>", species),
+                }
+            }
+            Value::List(list) => {
+                let items: Vec<String> = list.read().unwrap().iter().map(|v| v.describe()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Value::String(s) => format!("\"{}\"", s),
+            other => other.to_string(),
         }
     }
 
@@ -98,11 +308,31 @@ impl Value {
 
 #[derive(Debug, Clone)]
 pub struct Individual {
+    pub id: u64,                        //stays the same through copies and snapshots (the gui inspector follows it)
     pub species: String,                //what species is this? e.g., "ant"
     pub env: Arc<RwLock<Environment>>,  //its personal data (x, y, energy, etc.)
 }
 
+//ids only have to be unique, so one counter is shared by all worlds
+static NEXT_INDIVIDUAL_ID: AtomicU64 = AtomicU64::new(1);
+
 impl Individual {
+    //a new individual with a fresh id
+    pub fn new(species: String, env: Arc<RwLock<Environment>>) -> Self {
+        let id = NEXT_INDIVIDUAL_ID.fetch_add(1, Ordering::Relaxed);
+        Self { id, species, env }
+    }
+
+    //the id a new individual will get next, saved with checkpoints
+    pub fn next_id() -> u64 {
+        NEXT_INDIVIDUAL_ID.load(Ordering::Relaxed)
+    }
+
+    //make sure new individuals never reuse an id restored from a checkpoint
+    pub fn reserve_ids(next: u64) {
+        NEXT_INDIVIDUAL_ID.fetch_max(next, Ordering::Relaxed);
+    }
+
     pub fn deep_clone(&self) -> Self {
         let new_env = Environment::new();
         let old_ptr = self.env.clone();
@@ -118,7 +348,7 @@ impl Individual {
 
         for v in new_store.values_mut() { fix(v, &old_ptr, &new_env); }
         new_env.write().unwrap().store = new_store;
-        Self { species: self.species.clone(), env: new_env }
+        Self { id: self.id, species: self.species.clone(), env: new_env }
     }
 }
 
@@ -128,29 +358,117 @@ impl Individual {
 
 #[derive(Debug, Clone)]
 pub struct GenerationSnapshot {
+    pub generation: i32,
     pub avg_fitness: i32,
     pub best_fitness: i32,
+    pub worst_fitness: i32,
     pub individuals: Vec<Individual>,
     pub step_history: Vec<Vec<Individual>>,
 }
 
+//runtime error - raised in strict mode when an expression can't be evaluated
+//(division by zero, bad index, unknown function, field of a non-object...)
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub line: usize,
+    pub message: String,
+    pub routine: String,           //block it happened in, e.g. "ROUTINE hunt" or "FITNESS"
+    pub individual: Option<usize>, //index of the individual running the code
+}
+
+impl RuntimeError {
+    pub fn new(line: usize, message: String) -> Self {
+        Self { line, message, routine: String::new(), individual: None }
+    }
+
+    //how a failure that didn't stop the run (outside strict mode) is reported
+    pub fn as_warning(&self) -> String {
+        format!("Runtime Warning at line {}: {}", self.line, self.message)
+    }
+
+    //fill in where the failing code was running
+    pub fn within(mut self, routine: &str, individual: Option<usize>) -> Self {
+        self.routine = routine.to_string();
+        self.individual = individual;
+        self
+    }
+}
+
+//any value as a string for printing (value.to_string())
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Object(_) => write!(f, "[Object]"),
+            Value::List(l) => write!(f, "{:?}", l.read().unwrap()),
+            Value::Environment => write!(f, "[Environment]"),
+            Value::GridRow(x) => write!(f, "[GridRow {}]", x),
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Runtime Error at line {}", self.line)?;
+        if !self.routine.is_empty() {
+            write!(f, " in {}", self.routine)?;
+        }
+        if let Some(i) = self.individual {
+            write!(f, " (individual {})", i)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
 //standard exp + bexp setup
 //exp
 
+//where an expression is written: its first line and the columns it covers there
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub end_col: usize, //column after the last character
+}
+
+impl Span {
+    //from the start of `self` to the end of `other`, if that is on the same line
+    pub fn to(self, other: Span) -> Span {
+        if other.line == self.line && other.end_col > self.col {
+            Span { end_col: other.end_col, ..self }
+        } else {
+            self
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Exp {
-    Int(i32, usize),                                 //literal number: 42
-    Bool(bool, usize),                               //literal boolean: true
-    StringLiteral(String, usize),                    //literal text: "hello"
-    Var(String, usize),                              //variable name: x
-    Dot(Box<Exp>, String, usize),                    //field access: self.energy
-    BinaryOp(Box<Exp>, String, Box<Exp>, usize),     //math: a + b
-    Call(String, Vec<Exp>, usize),                   //function call: random(1, 10)
-    Index(Box<Exp>, Box<Exp>, usize),                //array access: list[i]
-    List(Vec<Exp>, usize),                           //list literal: [1, 2, 3]
+    Int(i32, Span),                                 //literal number: 42
+    Float(f64, Span),                               //literal decimal: 0.25
+    Bool(bool, Span),                               //literal boolean: true
+    StringLiteral(String, Span),                    //literal text: "hello"
+    Var(String, Span),                              //variable name: x
+    Dot(Box<Exp>, String, Span),                    //field access: self.energy
+    BinaryOp(Box<Exp>, String, Box<Exp>, Span),     //math: a + b
+    Call(String, Vec<Exp>, Span),                   //function call: random(1, 10)
+    Index(Box<Exp>, Box<Exp>, Span),                //array access: list[i]
+    List(Vec<Exp>, Span),                           //list literal: [1, 2, 3]
 }
 
 impl Exp {
+    pub fn span(&self) -> Span {
+        match self {
+            Exp::Int(_, span) | Exp::Float(_, span) | Exp::Bool(_, span) |
+            Exp::StringLiteral(_, span) | Exp::Var(_, span) | Exp::Dot(_, _, span) |
+            Exp::BinaryOp(_, _, _, span) | Exp::Call(_, _, span) | Exp::Index(_, _, span) |
+            Exp::List(_, span) => *span,
+        }
+    }
 }
 
 //bexp
@@ -191,10 +509,24 @@ pub enum Command {
     Return(Exp, usize),
     Print(Vec<Exp>, usize),
     Spawn { species: String, x: Exp, y: Exp, line: usize },
+    Die(usize),
     Exp(Exp, usize),
 }
 
 impl Command {
+    pub fn line(&self) -> usize {
+        match self {
+            Command::Assign { line, .. } |
+            Command::If { line, .. } |
+            Command::While { line, .. } |
+            Command::For { line, .. } |
+            Command::Spawn { line, .. } => *line,
+            Command::Return(_, line) |
+            Command::Print(_, line) |
+            Command::Die(line) |
+            Command::Exp(_, line) => *line,
+        }
+    }
 }
 
 //program structure - the parsed program
@@ -202,7 +534,7 @@ impl Command {
 //definition of a species 
 #[derive(Debug, Clone)]
 pub struct SpeciesDef {
-    pub properties: HashMap<String, Exp>,  //default property values
+    pub properties: Vec<(String, Exp)>,    //default property values, in source order
     pub routine_call: String,              //which routine to run each step
 }
 
@@ -213,6 +545,15 @@ pub struct RoutineDef {
     pub body: Vec<Command>,
 }
 
+//a user function with parameters: FUNCTION name(a, b) { ... }
+#[derive(Debug, Clone)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Command>,
+    pub line: usize,
+}
+
 //a mutation/crossover rule
 #[derive(Debug, Clone)]
 pub struct MutationRule {
@@ -221,11 +562,24 @@ pub struct MutationRule {
     pub body: Option<Vec<Command>>, //commands
 }
 
+//how parents for the next generation are picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    Truncation,        //the best survivors take turns as parents
+    Tournament(usize), //best of n random survivors
+    Roulette,          //chance proportional to fitness
+    Rank,              //chance proportional to position in the ranking
+}
+
 //settings for the evolutionary process
 #[derive(Debug, Clone)]
 pub struct EvolveBlock {
     pub generations: i32,
     pub instances: i32,
+    pub seed: Option<u64>, //run seed, picked at startup if not given
+    pub selection: Selection,
+    pub elitism: i32,           //best instances carried over unchanged
+    pub survivors: Option<i32>, //how many of the best instances may become parents
 }
 
 impl Default for EvolveBlock {
@@ -233,10 +587,63 @@ impl Default for EvolveBlock {
         Self {
             generations: 1,
             instances: 1,
+            seed: None,
+            selection: Selection::Truncation,
+            elitism: 0,
+            survivors: None,
+        }
+    }
+}
+
+impl EvolveBlock {
+    //parent pool size: half the instances for truncation, everyone otherwise
+    pub fn survivor_count(&self) -> i32 {
+        match self.survivors {
+            Some(n) => n,
+            None if self.selection == Selection::Truncation => (self.instances / 2).max(1),
+            None => self.instances,
         }
     }
 }
 
+//what happens when an individual moves onto a cell someone else is standing on.
+//set with `occupancy: stack` or `occupancy: block` in the ENVIRONMENT block
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Occupancy {
+    #[default]
+    Stack, //any number of individuals can share a cell
+    Block, //a move (or spawn) ending on an occupied cell is undone, the mover stays put
+}
+
+//how individuals take their turn in a step.
+//set with `update: sequential | synchronous | random_order` in the ENVIRONMENT block
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UpdateMode {
+    #[default]
+    Sequential,  //in spawn order, each one sees the changes made before it
+    Synchronous, //everyone reads the previous step's state, writes show up once the step is done
+    RandomOrder, //like sequential but in a new shuffled order every step
+}
+
+//order individuals act in within a step.
+//set with `activation: spawn | shuffled | priority(property)` in the ENVIRONMENT block
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Activation {
+    #[default]
+    Spawn,            //the order they were spawned in
+    Shuffled,         //a new random order every step (same as update: random_order)
+    Priority(String), //highest value of the property first, ties in random order
+}
+
+//unit of the positions and sizes given to draw_* in VISUALIZE.
+//set with `coordinates: pixels | cells` in the ENVIRONMENT block
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Coordinates {
+    #[default]
+    Pixels, //canvas pixels, the canvas is 600x600 at zoom 1
+    Cells,  //world cells, scaled so the whole world fills the canvas
+}
+
 //fitness calculation definition
 #[derive(Debug, Clone, Default)]
 pub struct FitnessBlock {
@@ -250,9 +657,14 @@ pub struct Program {
     pub env_width: i32,
     pub env_height: i32,
     pub env_steps: i32,
+    pub env_occupancy: Occupancy,
+    pub env_update: UpdateMode,
+    pub env_activation: Activation,
+    pub env_coordinates: Coordinates,
     
     //program blocks
     pub routines_block: HashMap<String, RoutineDef>,
+    pub functions_block: HashMap<String, FunctionDef>,
     pub species_block: HashMap<String, SpeciesDef>,
     pub spawns_block: Vec<Command>,
     pub mutations_block: Vec<MutationRule>,
@@ -260,6 +672,10 @@ pub struct Program {
     pub evolve_block: EvolveBlock,
     pub visualize_block: Vec<Command>,
     pub visualize: bool,
+    pub strict: bool, //report runtime errors instead of quietly using 0
+    pub bytecode: Option<Arc<Bytecode>>, //compiled blocks, run by the vm instead of the tree-walker (--vm)
+    pub profile: bool, //time routines and blocks (--profile)
+    pub debug: bool, //commands check in with the debugger (--debug)
 }
 
 impl Default for Program {
@@ -268,7 +684,12 @@ impl Default for Program {
             env_width: 100,
             env_height: 100,
             env_steps: 100,
+            env_occupancy: Occupancy::Stack,
+            env_update: UpdateMode::Sequential,
+            env_activation: Activation::Spawn,
+            env_coordinates: Coordinates::Pixels,
             routines_block: HashMap::new(),
+            functions_block: HashMap::new(),
             species_block: HashMap::new(),
             spawns_block: Vec::new(),
             mutations_block: Vec::new(),
@@ -276,6 +697,10 @@ impl Default for Program {
             evolve_block: EvolveBlock::default(),
             visualize_block: Vec::new(),
             visualize: true, // visualize by default
+            strict: false,
+            bytecode: None,
+            profile: false,
+            debug: false,
         }
     }
 }
@@ -302,11 +727,17 @@ pub struct World {
     pub fitness: i32,
     pub record_history: bool,
     pub history: Vec<Vec<Individual>>,
+    pub rng: ChaCha12Rng, //own rng so runs don't depend on thread scheduling
+    pub error: Option<RuntimeError>, //first runtime error (strict mode), stops the world
+    pub warnings: Vec<RuntimeError>, //failures outside strict mode, printed by whoever runs the world
+    pub profile: Profile, //timings since the simulation last collected them (--profile)
 }
 
 impl World {
     pub fn new(program: Arc<Program>, id: i32) -> Self {
+        let seed = derive_seed(program.evolve_block.seed.unwrap_or(0), id, 0);
         Self {
+            rng: ChaCha12Rng::seed_from_u64(seed),
             width: program.env_width,
             height: program.env_height,
             individuals: Vec::new(),
@@ -316,6 +747,9 @@ impl World {
             fitness: 0,
             record_history: false,
             history: Vec::new(),
+            error: None,
+            warnings: Vec::new(),
+            profile: Profile::default(),
         }
     }
 
@@ -331,6 +765,27 @@ impl World {
             fitness: self.fitness,
             record_history: self.record_history,
             history: std::mem::take(&mut self.history),
+            rng: self.rng.clone(),
+            error: self.error.take(),
+            warnings: std::mem::take(&mut self.warnings),
+            profile: std::mem::take(&mut self.profile),
         }
     }
+
+    //restart the rng stream for the current generation
+    pub fn reseed(&mut self) {
+        let seed = derive_seed(self.program.evolve_block.seed.unwrap_or(0), self.id, self.generation);
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+}
+
+//mix the run seed, instance id and generation into one world seed
+//(splitmix64 finalizer so neighbouring ids get unrelated streams)
+pub fn derive_seed(seed: u64, id: i32, generation: i32) -> u64 {
+    let mut z = seed
+        ^ (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (generation as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
 }
@@ -0,0 +1,370 @@
+//vm.rs - runs the bytecode from bytecode.rs (--vm)
+//gives the same results as Command::execute: everything except variable access
+//and control flow goes through the same helpers in eval.rs.
+
+use std::sync::{Arc, RwLock};
+
+use crate::types::*;
+use crate::bytecode::*;
+use crate::eval::*;
+
+//run one top-level command of a block, compiled if the program was compiled
+pub fn run_command(
+    block: Block,
+    index: usize,
+    cmd: &Command,
+    env: Arc<RwLock<Environment>>,
+    individuals: &[Individual],
+    spawner: &mut Vec<Individual>,
+    program: &Program,
+) -> Result<Option<Value>, RuntimeError> {
+    if let Some(code) = &program.bytecode
+        && let Some(chunk) = code.block(block).and_then(|chunks| chunks.get(index)) {
+        return run(chunk, &env, individuals, spawner, program, code);
+    }
+    cmd.execute(env, individuals, spawner, program)
+}
+
+//a variable: env's own slot, then the environment keyword, then self's slot.
+//the same order as eval::lookup_var
+fn load(env: &Arc<RwLock<Environment>>, layout: &Arc<Layout>, slot: usize) -> Value {
+    let me = {
+        let env_ref = env.read().unwrap();
+        if let Some(value) = env_ref.store.slot(layout, slot) {
+            return value.clone();
+        }
+        if slot == Layout::ENVIRONMENT {
+            return Value::Environment;
+        }
+        match env_ref.store.slot(layout, Layout::SELF) {
+            Some(Value::Object(me)) => me.clone(),
+            _ => return Value::Int(0),
+        }
+    };
+    let value = me.read().unwrap().store.slot(layout, slot).cloned();
+    value.unwrap_or(Value::Int(0))
+}
+
+//obj.field, same as eval::read_field
+fn load_field(obj: Value, layout: &Arc<Layout>, slot: usize, line: usize) -> Value {
+    match obj {
+        Value::Object(obj_env) => obj_env.read().unwrap().store.slot(layout, slot).cloned().unwrap_or(Value::Int(0)),
+        other => read_field(other, &layout.names[slot], line),
+    }
+}
+
+//variable = value, same as eval::set_var
+fn store(env: &Arc<RwLock<Environment>>, layout: &Arc<Layout>, slot: usize, value: Value) {
+    let live = scratch_owner(env);
+    if slot == Layout::X || slot == Layout::Y {
+        track_move(live.as_ref().unwrap_or(env), &layout.names[slot], &value);
+    }
+    if let Some(live) = live {
+        live.write().unwrap().store.set_slot(layout, slot, value.clone());
+    }
+    env.write().unwrap().store.set_slot(layout, slot, value);
+}
+
+fn pop_n(stack: &mut Vec<Value>, count: usize) -> Vec<Value> {
+    stack.split_off(stack.len() - count)
+}
+
+//execute a chunk until it ends (Ok(None)) or returns a value
+fn run(
+    chunk: &Chunk,
+    env: &Arc<RwLock<Environment>>,
+    individuals: &[Individual],
+    spawner: &mut Vec<Individual>,
+    program: &Program,
+    code: &Bytecode,
+) -> Result<Option<Value>, RuntimeError> {
+    let layout = &code.layout;
+    let mut stack: Vec<Value> = Vec::new();
+    let mut pc = 0;
+
+    while pc < chunk.code.len() {
+        let op = &chunk.code[pc];
+        pc += 1;
+        match op {
+            //values
+            Op::Int(v) => stack.push(Value::Int(*v)),
+            Op::Float(f) => stack.push(Value::Float(*f)),
+            Op::Bool(b) => stack.push(Value::Bool(*b)),
+            Op::Str(s) => stack.push(Value::String(s.clone())),
+            Op::LoadSlot(slot) => stack.push(load(env, layout, *slot)),
+            Op::Field(slot, line) => {
+                let obj = stack.pop().unwrap();
+                stack.push(load_field(obj, layout, *slot, *line));
+            }
+            Op::MakeList(count) => {
+                let items = pop_n(&mut stack, *count);
+                stack.push(Value::List(Arc::new(RwLock::new(items))));
+            }
+            Op::Index(line) => {
+                let idx = stack.pop().unwrap().to_int();
+                let list = stack.pop().unwrap();
+                stack.push(index_value(list, idx, *line, individuals));
+            }
+            Op::ToInt => {
+                let value = stack.pop().unwrap();
+                stack.push(Value::Int(value.to_int()));
+            }
+            Op::Arith(arith, line) => {
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                stack.push(binary_op(&left, arith.symbol(), &right, *line));
+            }
+            Op::Compare(compare) => {
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                let order = compare_numbers(&left, &right);
+                let result = match compare {
+                    Compare::Equal => values_are_equal(&left, &right),
+                    Compare::NotEqual => !values_are_equal(&left, &right),
+                    Compare::Greater => matches!(order, Some(std::cmp::Ordering::Greater)),
+                    Compare::Less => matches!(order, Some(std::cmp::Ordering::Less)),
+                    Compare::GreaterEqual => matches!(order, Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)),
+                    Compare::LessEqual => matches!(order, Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)),
+                };
+                stack.push(Value::Bool(result));
+            }
+
+            //control flow
+            Op::Jump(target) => pc = *target,
+            Op::JumpIfFalse(target) => {
+                if !matches!(stack.pop(), Some(Value::Bool(true))) {
+                    pc = *target;
+                }
+            }
+            Op::JumpIfTrue(target) => {
+                if matches!(stack.pop(), Some(Value::Bool(true))) {
+                    pc = *target;
+                }
+            }
+            Op::Pop => { stack.pop(); }
+            Op::Check => check_error()?,
+            Op::Return => return Ok(stack.pop()),
+
+            //commands
+            Op::StoreSlot(slot) => {
+                let value = stack.pop().unwrap();
+                store(env, layout, *slot, value);
+            }
+            Op::SetField(slot, line) => {
+                let obj = stack.pop().unwrap();
+                let value = stack.pop().unwrap();
+                match obj {
+                    Value::Object(obj_env) => {
+                        let obj_env = write_target(obj_env);
+                        if *slot == Layout::X || *slot == Layout::Y {
+                            track_move(&obj_env, &layout.names[*slot], &value);
+                        }
+                        obj_env.write().unwrap().store.set_slot(layout, *slot, value);
+                    }
+                    other => raise(*line, format!("cannot set field '{}' on {}", layout.names[*slot], other.type_name())),
+                }
+            }
+            Op::SkipUnlessList(target, line) => {
+                if !matches!(stack.last(), Some(Value::List(_))) {
+                    let other = stack.pop().unwrap();
+                    stack.pop();
+                    raise(*line, format!("cannot index into {}", other.type_name()));
+                    pc = *target;
+                }
+            }
+            Op::SetIndex(line) => {
+                let idx = stack.pop().unwrap().to_int();
+                let list = stack.pop().unwrap();
+                let value = stack.pop().unwrap();
+                if let Value::List(list) = list {
+                    let list = write_list(list);
+                    let mut borrowed = list.write().unwrap();
+                    if idx >= 0 && (idx as usize) < borrowed.len() {
+                        borrowed[idx as usize] = value;
+                    } else {
+                        raise(*line, format!("index {} out of range for list of length {}", idx, borrowed.len()));
+                    }
+                }
+            }
+            Op::Spawn(species) => {
+                let y_pos = stack.pop().unwrap().to_number();
+                let x_pos = stack.pop().unwrap().to_number();
+                if !spawn_allowed(&x_pos, &y_pos, individuals, spawner, program) {
+                    continue;
+                }
+                let new_env = Environment::with_store(Store::with_layout(layout));
+                new_env.write().unwrap().store.set_slot(layout, Layout::SPECIES, Value::String(species.clone()));
+                if let Some(defaults) = code.species.get(species) {
+                    for (prop_slot, prop_chunk) in defaults {
+                        let value = run(prop_chunk, &new_env, individuals, spawner, program, code)?
+                            .unwrap_or(Value::Int(0));
+                        new_env.write().unwrap().store.set_slot(layout, *prop_slot, value);
+                    }
+                }
+                {
+                    let mut env_mut = new_env.write().unwrap();
+                    env_mut.store.set_slot(layout, Layout::X, x_pos);
+                    env_mut.store.set_slot(layout, Layout::Y, y_pos);
+                }
+                spawner.push(Individual::new(species.clone(), new_env));
+            }
+            Op::Die => die(env),
+            Op::Print(count) => {
+                let parts: Vec<String> = pop_n(&mut stack, *count).iter().map(|v| v.to_string()).collect();
+                println!("{}", parts.join(" "));
+            }
+            Op::ForNext(slot, target) => {
+                let start = stack.last().unwrap().to_int() as usize;
+                let next = (start..individuals.len()).find(|&i| !individuals[i].env.read().unwrap().dead);
+                match next {
+                    Some(i) => {
+                        *stack.last_mut().unwrap() = Value::Int(i as i32 + 1);
+                        store(env, layout, *slot, Value::Object(individuals[i].env.clone()));
+                    }
+                    None => {
+                        stack.pop();
+                        pc = *target;
+                    }
+                }
+            }
+
+            //builtins
+            Op::Len => {
+                let value = match stack.pop().unwrap() {
+                    Value::List(list) => Value::Int(list.read().unwrap().len() as i32),
+                    _ => Value::Int(0),
+                };
+                stack.push(value);
+            }
+            Op::IfList(target) => {
+                if !matches!(stack.last(), Some(Value::List(_))) {
+                    *stack.last_mut().unwrap() = Value::Int(0);
+                    pc = *target;
+                }
+            }
+            Op::ListPush => {
+                let value = stack.pop().unwrap();
+                if let Some(Value::List(list)) = stack.pop() {
+                    write_list(list).write().unwrap().push(value);
+                }
+                stack.push(Value::Int(0));
+            }
+            Op::ListPop => {
+                let value = match stack.pop().unwrap() {
+                    Value::List(list) => write_list(list).write().unwrap().pop().unwrap_or(Value::Int(0)),
+                    _ => Value::Int(0),
+                };
+                stack.push(value);
+            }
+            Op::GetAt | Op::AllAt => {
+                let y = stack.pop().unwrap().to_int();
+                let x = stack.pop().unwrap().to_int();
+                let found = if matches!(op, Op::GetAt) { get_at(x, y, individuals) } else { all_at(x, y, individuals) };
+                stack.push(found);
+            }
+            Op::Nearest | Op::CountInRadius => {
+                let radius = stack.pop().unwrap().to_int();
+                let species = stack.pop().unwrap().to_string();
+                let found = if matches!(op, Op::Nearest) {
+                    nearest(env, &species, radius, individuals)
+                } else {
+                    count_in_radius(env, &species, radius, individuals)
+                };
+                stack.push(found);
+            }
+            Op::Neighbors(metric) => {
+                let radius = stack.pop().unwrap().to_int();
+                stack.push(neighbors(env, radius, *metric, individuals));
+            }
+            Op::Kill => {
+                kill(stack.pop().unwrap());
+                stack.push(Value::Int(0));
+            }
+            Op::Dist => {
+                let obj2 = stack.pop().unwrap();
+                let obj1 = stack.pop().unwrap();
+                stack.push(dist(&obj1, &obj2));
+            }
+            Op::Random => {
+                let max = stack.pop().unwrap();
+                let min = stack.pop().unwrap();
+                stack.push(random_between(&min, &max));
+            }
+            Op::DrawRect(count) | Op::DrawLine(count) | Op::DrawCircle(count) => {
+                let args = pop_n(&mut stack, *count);
+                let float = |i: usize, default: f32| args.get(i).map_or(default, |v| v.to_float() as f32);
+                let color = |i: usize| args.get(i).map_or(255, |v| v.to_int() as u8);
+                let cmd = match op {
+                    Op::DrawRect(_) => DrawCmd::Rect {
+                        x: float(0, 0.0), y: float(1, 0.0), w: float(2, 0.0), h: float(3, 0.0),
+                        r: color(4), g: color(5), b: color(6),
+                    },
+                    Op::DrawLine(_) => DrawCmd::Line {
+                        x1: float(0, 0.0), y1: float(1, 0.0), x2: float(2, 0.0), y2: float(3, 0.0),
+                        r: color(4), g: color(5), b: color(6), thickness: float(7, 1.0),
+                    },
+                    _ => DrawCmd::Circle {
+                        x: float(0, 0.0), y: float(1, 0.0), radius: float(2, 0.0),
+                        r: color(3), g: color(4), b: color(5),
+                    },
+                };
+                DRAW_COMMANDS.with(|cmds| cmds.borrow_mut().push(cmd));
+                stack.push(Value::Int(0));
+            }
+            Op::CallCheck(func, target, line) => {
+                if check_call_depth(&code.functions[*func].name, *line, program.strict).is_none() {
+                    stack.push(Value::Int(0));
+                    pc = *target;
+                }
+            }
+            Op::Call(func, count) => {
+                let args = pop_n(&mut stack, *count);
+                let result = call_function(&code.functions[*func], args, env, individuals, program, code);
+                stack.push(result);
+            }
+            Op::UnknownFunction(name, line) => {
+                raise(*line, format!("unknown function '{}'", name));
+                stack.push(Value::Int(0));
+            }
+        }
+    }
+    Ok(None)
+}
+
+//same as the tree-walker's call_function, with the arguments already evaluated
+fn call_function(
+    func: &CompiledFunction,
+    args: Vec<Value>,
+    env: &Arc<RwLock<Environment>>,
+    individuals: &[Individual],
+    program: &Program,
+    code: &Bytecode,
+) -> Value {
+    //the call's frame: a store with a slot for every local
+    let layout = &code.layout;
+    let local_env = Environment::with_store(Store::with_layout(layout));
+    {
+        let mut local = local_env.write().unwrap();
+        for (&param, value) in func.params.iter().zip(args) {
+            local.store.set_slot(layout, param, value);
+        }
+        if let Some(self_val) = env.read().unwrap().store.slot(layout, Layout::SELF) {
+            local.store.set_slot(layout, Layout::SELF, self_val.clone());
+        }
+    }
+
+    let depth = call_depth();
+    set_call_depth(depth + 1);
+    let mut spawner = Vec::new();
+    let result = match run(&func.body, &local_env, individuals, &mut spawner, program, code) {
+        Ok(value) => value.unwrap_or(Value::Int(0)),
+        Err(err) => {
+            pass_error(err);
+            Value::Int(0)
+        }
+    };
+    set_call_depth(depth);
+
+    local_env.write().unwrap().store.clear();
+    result
+}
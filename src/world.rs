@@ -2,25 +2,44 @@
 // running steps calculating fitness (and managing evolution -- no more, moved to evolution.rs)
 
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use rand::Rng;
+use rand::seq::SliceRandom;
 
 use crate::types::*;
-use crate::eval::{GRID_CACHE, WORLD_DIMENSIONS};
+use crate::spatial::SpatialIndex;
+use crate::bytecode::Block;
+use crate::vm::run_command;
+use crate::eval::{GRID_CACHE, SYNC_BUFFERS, SyncBuffers, WORLD_DIMENSIONS, RNG, commit_moves, env_key, list_key, install_program, take_warnings};
 
 impl World {
     //run spawn block to create initial individuals
     pub fn spawn(&mut self) {
+        //occupancy: block wraps spawn positions onto the world
+        WORLD_DIMENSIONS.with(|d| *d.borrow_mut() = (self.width, self.height));
+        self.swap_thread_state();
         let mut spawner = Vec::new();
         let env = Environment::new();
         
-        for cmd in &self.program.spawns_block {
-            cmd.execute(env.clone(), &self.individuals, &mut spawner, &self.program);
+        for (c, cmd) in self.program.spawns_block.iter().enumerate() {
+            if let Err(e) = run_command(Block::Spawn, c, cmd, env.clone(), &self.individuals, &mut spawner, &self.program) {
+                self.record_error(e.within("SPAWN", None));
+                break;
+            }
         }
         
         self.individuals.extend(spawner);
+        self.swap_thread_state();
     }
 
     //run one simulation step for all individuals
     pub fn step(&mut self) {
+        //a world that hit a runtime error stays stopped
+        if self.error.is_some() {
+            return;
+        }
+
         if self.record_history {
             let mut step_snapshot = Vec::new();
             for ind in &self.individuals {
@@ -32,37 +51,84 @@ impl World {
 
         //set up world dimensions
         WORLD_DIMENSIONS.with(|d| *d.borrow_mut() = (self.width, self.height));
-        
+        self.swap_thread_state();
+
+        //synchronous: routines read a copy of the state from before the step
+        let synchronous = self.program.env_update == UpdateMode::Synchronous;
+        let previous = if synchronous { self.begin_synchronous() } else { Vec::new() };
+        let readable = if synchronous { &previous } else { &self.individuals };
+
         //build position cache
-        self.build_grid_cache();
+        let start = self.program.profile.then(Instant::now);
+        self.build_grid_cache(readable);
+        self.profile.record("build_grid_cache", start);
+
+        let order = self.activation_order(readable);
 
         let mut spawner = Vec::new();
         
         //optimization: use a shared snapshot for read-only environment access if needed
         //but for routine execution just iterate
-        for i in 0..self.individuals.len() {
+        for i in order {
+            //killed earlier in this step, don't act anymore
+            if readable[i].env.read().unwrap().dead {
+                continue;
+            }
+
             //get the species definition
             let species_name = self.individuals[i].species.clone();
             if let Some(species_def) = self.program.species_block.get(&species_name) {
                 //get the routine to execute
                 if let Some(routine) = self.program.routines_block.get(&species_def.routine_call) {
-                    let env = self.individuals[i].env.clone();
-                    env.write().unwrap().store.insert("self".to_string(), Value::Object(env.clone()));
+                    //in a synchronous step the routine runs in a scratch env and self is
+                    //the copy from before the step, so bare names read the previous state
+                    //too. writes go to the live individual (write_target, set_var)
+                    let env = if synchronous {
+                        Environment::with_store(self.individuals[i].env.read().unwrap().store.empty_like())
+                    } else {
+                        self.individuals[i].env.clone()
+                    };
+                    let me = readable[i].env.clone();
+                    env.write().unwrap().store.insert("self".to_string(), Value::Object(me));
+                    if synchronous {
+                        self.begin_scratch(&env, i);
+                    }
                     
-                    for cmd in &routine.body {
+                    let start = self.program.profile.then(Instant::now);
+                    for (c, cmd) in routine.body.iter().enumerate() {
                         //pass individuals slice directly instead of cloning
-                        cmd.execute(env.clone(), &self.individuals, &mut spawner, &self.program);
+                        let block = Block::Routine(&species_def.routine_call);
+                        if let Err(e) = run_command(block, c, cmd, env.clone(), readable, &mut spawner, &self.program) {
+                            self.error = Some(e.within(&format!("ROUTINE {}", routine.name), Some(i)));
+                            break;
+                        }
+                    }
+                    if start.is_some() {
+                        self.profile.record(&format!("ROUTINE {}", routine.name), start);
+                    }
+                    if synchronous {
+                        end_scratch(&env);
                     }
                 }
             }
+            //the occupancy policy looks at where this individual's moves ended
+            commit_moves();
+            if self.error.is_some() {
+                break;
+            }
         }
         
         self.individuals.extend(spawner);
+        if synchronous {
+            self.end_synchronous(previous);
+        }
+        self.remove_dead();
         self.clear_grid_cache();
+        self.swap_thread_state();
     }
 
     //calculate fitness for a single individual
-    pub fn calculate_fitness(&self, ind: &Individual) -> i32 {
+    pub fn calculate_fitness(&self, ind: &Individual) -> Result<i32, RuntimeError> {
         let fitness_def = &self.program.fitness_block;
         
         if !fitness_def.commands.is_empty() {
@@ -72,10 +138,10 @@ impl World {
             env.write().unwrap().store.insert("self".to_string(), Value::Object(ind.env.clone()));
             let mut spawner = Vec::new();
             
-            for cmd in &fitness_def.commands {
-                let result = cmd.execute(env.clone(), &self.individuals, &mut spawner, &self.program);
+            for (c, cmd) in fitness_def.commands.iter().enumerate() {
+                let result = run_command(Block::Fitness, c, cmd, env.clone(), &self.individuals, &mut spawner, &self.program)?;
                 if let Some(val) = result {
-                    return val.to_int();
+                    return Ok(val.to_int());
                 }
             }
             
@@ -83,21 +149,37 @@ impl World {
             let store = &env.read().unwrap().store;
             let score = store.get("score").map_or(0, |v| v.to_int());
             if score != 0 {
-                return score;
+                return Ok(score);
             }
         }
-        0
+        Ok(0)
     }
 
     //calculate fitness for all individuals and return best score
     pub fn calculate_total_fitness(&mut self) -> i32 {
+        if self.error.is_some() {
+            return 0;
+        }
         WORLD_DIMENSIONS.with(|d| *d.borrow_mut() = (self.width, self.height));
-        self.build_grid_cache();
+        self.swap_thread_state();
+        let start = self.program.profile.then(Instant::now);
+        self.build_grid_cache(&self.individuals);
+        self.profile.record("build_grid_cache", start);
 
         let mut best = 0;
         
         for i in 0..self.individuals.len() {
-            let score = self.calculate_fitness(&self.individuals[i]);
+            let start = self.program.profile.then(Instant::now);
+            let fitness = self.calculate_fitness(&self.individuals[i]);
+            commit_moves();
+            self.profile.record("FITNESS", start);
+            let score = match fitness {
+                Ok(score) => score,
+                Err(e) => {
+                    self.record_error(e.within("FITNESS", Some(i)));
+                    break;
+                }
+            };
             self.individuals[i].env.write().unwrap().store.insert("fitness".to_string(), Value::Int(score));
             if score > best {
                 best = score;
@@ -105,57 +187,181 @@ impl World {
         }
         
         self.fitness = best;
+        self.remove_dead();
         self.clear_grid_cache();
+        self.swap_thread_state();
         best
     }
 
     //apply mutations to all individuals
+    #[allow(clippy::collapsible_if)]
     pub fn mutate(&mut self) {
         if self.individuals.is_empty() {
             return;
         }
 
         //mutate everyone no selection
+        self.swap_thread_state();
         let individuals_snapshot = self.individuals.clone();
-        for offspring in &mut self.individuals {
+        let mut error = None;
+        for (i, offspring) in self.individuals.iter_mut().enumerate() {
             //apply mutation rule
             if let Some(rule) = self.program.mutations_block.iter()
                 .find(|r| r.action == "mutation") 
             {
-                if rand::random::<f32>() < rule.probability {
+                if RNG.with(|rng| rng.borrow_mut().r#gen::<f32>()) < rule.probability {
                     if let Some(body) = &rule.body {
                         let env = offspring.env.clone();
                         env.write().unwrap().store.insert("self".to_string(), Value::Object(offspring.env.clone()));
                         
+                        let start = self.program.profile.then(Instant::now);
                         let mut spawner = Vec::new();
-                        for cmd in body {
-                            cmd.execute(env.clone(), &individuals_snapshot, &mut spawner, &self.program);
+                        for (c, cmd) in body.iter().enumerate() {
+                            let block = Block::Mutation("mutation");
+                            if let Err(e) = run_command(block, c, cmd, env.clone(), &individuals_snapshot, &mut spawner, &self.program) {
+                                error = Some(e.within("MUTATE mutation", Some(i)));
+                                break;
+                            }
                         }
+                        self.profile.record("MUTATE mutation", start);
                     }
                 }
             }
+            if error.is_some() {
+                break;
+            }
         }
+        if let Some(e) = error {
+            self.record_error(e);
+        }
+        self.remove_dead();
+        self.swap_thread_state();
     }
 
     //helper methods
 
-    //build grid cache for fast position lookups
-    fn build_grid_cache(&self) {
-        if self.individuals.len() <= 1 {
-            return;
+    //keep the first runtime error, the world stops running after it
+    pub fn record_error(&mut self, error: RuntimeError) {
+        if self.error.is_none() {
+            self.error = Some(error);
         }
-        
-        let mut grid_map = HashMap::new();
+    }
+
+    //drop individuals marked by die/kill. their stores are cleared so
+    //anyone still holding a reference to them can't keep them alive
+    pub fn remove_dead(&mut self) {
+        let mut alive = Vec::new();
+        for ind in self.individuals.drain(..) {
+            let dead = ind.env.read().unwrap().dead;
+            if dead {
+                ind.env.write().unwrap().store.clear();
+            } else {
+                alive.push(ind);
+            }
+        }
+        self.individuals = alive;
+    }
+
+    //who acts when in this step. shuffles use the world's rng so runs stay reproducible
+    fn activation_order(&self, individuals: &[Individual]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..individuals.len()).collect();
+        let activation = if self.program.env_update == UpdateMode::RandomOrder {
+            &Activation::Shuffled
+        } else {
+            &self.program.env_activation
+        };
+
+        match activation {
+            Activation::Spawn => {}
+            Activation::Shuffled => {
+                RNG.with(|rng| order.shuffle(&mut *rng.borrow_mut()));
+            }
+            Activation::Priority(property) => {
+                let mut priority = Vec::new();
+                for ind in individuals {
+                    let value = ind.env.read().unwrap().store.get(property).map_or(0.0, |v| v.to_float());
+                    priority.push(value);
+                }
+                //shuffle first so the stable sort leaves ties in random order
+                RNG.with(|rng| order.shuffle(&mut *rng.borrow_mut()));
+                order.sort_by(|a, b| priority[*b].total_cmp(&priority[*a]));
+            }
+        }
+        order
+    }
+
+    //copy everyone for a synchronous step. routines read the copies and write
+    //into the live individuals, so nobody sees a change made during this step
+    fn begin_synchronous(&self) -> Vec<Individual> {
+        let mut previous = Vec::new();
+        let mut buffers = SyncBuffers { live: HashMap::new(), previous: HashMap::new(), lists: HashMap::new(), claimed: HashMap::new(), scratch: HashMap::new() };
         for ind in &self.individuals {
-            let env_b = ind.env.read().unwrap();
-            let store = &env_b.store;
-            if let Some(Value::Int(x)) = store.get("x") {
-                if let Some(Value::Int(y)) = store.get("y") {
-                    grid_map.insert((*x, *y), ind.env.clone());
+            let copy = Environment::new();
+            buffers.live.insert(env_key(&copy), ind.env.clone());
+            buffers.previous.insert(env_key(&ind.env), copy.clone());
+            previous.push(Individual { id: ind.id, species: ind.species.clone(), env: copy });
+        }
+
+        //objects inside the copies point at the other copies
+        for (ind, copy) in self.individuals.iter().zip(&previous) {
+            let live = ind.env.read().unwrap();
+            let mut store = live.deep_copy_store();
+            for (key, value) in store.iter_mut() {
+                relink(value, &buffers.previous);
+                if let Some(live_value) = live.store.get(key) {
+                    pair_lists(value, live_value, &mut buffers.lists);
+                }
+            }
+            copy.env.write().unwrap().store = store;
+        }
+
+        SYNC_BUFFERS.with(|b| *b.borrow_mut() = Some(buffers));
+        previous
+    }
+
+    //bare variables set in the scratch env `env` also go to individual i
+    fn begin_scratch(&self, env: &Arc<RwLock<Environment>>, i: usize) {
+        let live = self.individuals[i].env.clone();
+        SYNC_BUFFERS.with(|b| {
+            if let Some(b) = b.borrow_mut().as_mut() {
+                b.scratch.insert(env_key(env), live);
+            }
+        });
+    }
+
+    //commit a synchronous step: references to the copies that were stored
+    //during the step now point at the live individuals, then the copies go
+    fn end_synchronous(&mut self, previous: Vec<Individual>) {
+        if let Some(buffers) = SYNC_BUFFERS.with(|b| b.borrow_mut().take()) {
+            for ind in &self.individuals {
+                let mut env = ind.env.write().unwrap();
+                for value in env.store.values_mut() {
+                    relink(value, &buffers.live);
                 }
             }
         }
-        GRID_CACHE.with(|cache| *cache.borrow_mut() = Some(grid_map));
+        for ind in &previous {
+            ind.env.write().unwrap().store.clear();
+        }
+    }
+
+    //exchange this world's rng with the thread's rng used by random()
+    //and make its program's FUNCTIONs callable on this thread.
+    //called in pairs: once before running commands, once after
+    pub fn swap_thread_state(&mut self) {
+        RNG.with(|rng| std::mem::swap(&mut *rng.borrow_mut(), &mut self.rng));
+        for warning in take_warnings() {
+            if !self.warnings.iter().any(|w| w.line == warning.line && w.message == warning.message) {
+                self.warnings.push(warning);
+            }
+        }
+        install_program(&self.program);
+    }
+
+    //build the spatial index for position lookups (get_at, all_at, environment[x][y])
+    fn build_grid_cache(&self, individuals: &[Individual]) {
+        let index = SpatialIndex::build(individuals, self.program.env_occupancy, (self.width, self.height));
+        GRID_CACHE.with(|cache| *cache.borrow_mut() = Some(index));
     }
 
     //clear the grid cache
@@ -163,3 +369,97 @@ impl World {
         GRID_CACHE.with(|cache| *cache.borrow_mut() = None);
     }
 }
+
+//map each list in a previous-state copy to the live list it was copied from
+fn pair_lists(copy: &Value, live: &Value, lists: &mut HashMap<usize, Arc<RwLock<Vec<Value>>>>) {
+    if let (Value::List(copy), Value::List(live)) = (copy, live) {
+        lists.insert(list_key(copy), live.clone());
+        for (c, l) in copy.read().unwrap().iter().zip(live.read().unwrap().iter()) {
+            pair_lists(c, l, lists);
+        }
+    }
+}
+
+//point objects at their counterpart in `map`, looking inside lists too
+fn relink(value: &mut Value, map: &HashMap<usize, Arc<RwLock<Environment>>>) {
+    match value {
+        Value::Object(obj) => {
+            if let Some(other) = map.get(&env_key(obj)) {
+                *value = Value::Object(other.clone());
+            }
+        }
+        Value::List(list) => {
+            for item in list.write().unwrap().iter_mut() {
+                relink(item, map);
+            }
+        }
+        _ => {}
+    }
+}
+
+//the routine that ran in `env` is done, its scratch env goes
+fn end_scratch(env: &Arc<RwLock<Environment>>) {
+    SYNC_BUFFERS.with(|b| {
+        if let Some(b) = b.borrow_mut().as_mut() {
+            b.scratch.remove(&env_key(env));
+        }
+    });
+    env.write().unwrap().store.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::Parser;
+    use crate::semantic::validate_program;
+    use crate::diagnostic::has_errors;
+    use crate::bytecode::compile;
+
+    //agent 0 zeroes everyone's power, agent 1 then reads its own as a bare name
+    const SYNCHRONOUS: &str = "
+        ENVIRONMENT { width: 10, height: 10, steps: 1, update: synchronous }
+        SPECIES {
+            Agent { power: 5, seen: 0, routine: act }
+            ROUTINE act {
+                if (self.x == 0) {
+                    for other in environment { other.power = 0; }
+                }
+                if (self.x == 1) { seen = power; }
+            }
+        }
+        SPAWN { spawn Agent @ (0, 0); spawn Agent @ (1, 0); }
+        FITNESS { return seen; }
+        MUTATE { mutation: { } }
+        EVOLVE { generations: 1, instances: 1 }
+    ";
+
+    fn world(vm: bool) -> World {
+        let (tokens, lex_errors) = lex(SYNCHRONOUS);
+        let (mut program, parse_errors) = Parser::new(tokens).parse_program();
+        assert!(!has_errors(&lex_errors) && !has_errors(&parse_errors));
+        assert!(!has_errors(&validate_program(&program)));
+        if vm {
+            program.bytecode = Some(Arc::new(compile(&program)));
+        }
+        let mut world = World::new(Arc::new(program), 0);
+        world.spawn();
+        world
+    }
+
+    fn property(world: &World, i: usize, name: &str) -> i32 {
+        world.individuals[i].env.read().unwrap().store.get(name).map_or(-1, |v| v.to_int())
+    }
+
+    #[test]
+    fn synchronous_step_reads_bare_names_from_the_previous_state() {
+        for vm in [false, true] {
+            let mut world = world(vm);
+            world.step();
+            assert!(world.error.is_none());
+            assert_eq!(property(&world, 1, "seen"), 5, "vm: {}", vm);
+            assert_eq!(property(&world, 0, "power"), 0, "vm: {}", vm);
+            assert_eq!(property(&world, 1, "power"), 0, "vm: {}", vm);
+        }
+    }
+}